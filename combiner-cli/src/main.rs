@@ -1,37 +1,872 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use harmony::{
+    AggregateOptions, ArrowSink, BScoreOptions, ColourRange, CombineOptions, CombineReport,
+    ConcordanceOptions, Controls, DoseResponseOptions, HeatmapOptions, ImageFormat, JsonlSink,
+    Layout, NormMethod, NormaliseOptions, NormaliseReport, PathTemplate, PlateFormat, PlateMap,
+    PlateNamePattern, QcOptions, QcThresholds, RecordSink, ReplicateGrouping, Statistic, Table,
+    TimeCourseOptions, TimeSource, TsvSink, WellOptions, WellStyle,
+};
 use std::{
     collections::HashMap,
     fs::File,
     io::{BufWriter, Write},
     path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
 
 #[derive(Parser)]
-#[clap(author, version)]
+#[clap(
+    author,
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 /// Combine exported harmony datafiles into a single TSV file
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
     /// directory to search for harmony files
-    #[clap(value_parser)]
-    input: PathBuf,
+    #[clap(value_parser, required = true)]
+    input: Option<PathBuf>,
     /// output file name, or stdout if not present
     #[clap(value_parser)]
     output: Option<PathBuf>,
+    /// Output file type: tsv, sqlite for a database with a table per population, xlsx
+    /// for an Excel workbook with a sheet per population, jsonl for JSON Lines, or arrow
+    /// for an Arrow IPC stream, with columns holding numbers in the first 8192 rows as
+    /// Float64
+    #[clap(long, value_parser, default_value = "tsv")]
+    format: OutputFormat,
     /// Create separate output files for each population
     #[clap(short, long, action, requires = "output")]
     separate: bool,
+    /// Print the combined columns, the files each comes from and the rows of each file,
+    /// without writing any output or running any analysis
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = &[
+            "format", "separate", "wide", "melt", "aggregate", "normalise", "qc", "b-score",
+            "dose-response", "concordance", "time-course", "matrix", "heatmap", "report",
+        ]
+    )]
+    dry_run: bool,
+    /// Join all populations of a plate into one row per well, prefixing columns by population
+    #[clap(long, action, conflicts_with = "separate")]
+    wide: bool,
+    /// Write one row per feature value (long format), with Feature, Value and Unit columns
+    #[clap(long, action, conflicts_with = "wide")]
+    melt: bool,
+    /// Summarise object-level rows into one row per well
+    #[clap(short, long, action, conflicts_with_all = &["wide", "melt"])]
+    aggregate: bool,
+    /// Feature to summarise when aggregating, can be repeated [default: all features]
+    #[clap(
+        long = "feature",
+        value_parser,
+        value_name = "NAME",
+        requires = "aggregate"
+    )]
+    features: Vec<String>,
+    /// Statistics to compute when aggregating: count, mean, median, sd, mad, q<percentile>
+    #[clap(
+        long,
+        value_parser,
+        use_value_delimiter = true,
+        default_value = "count,mean,median,sd,mad",
+        requires = "aggregate"
+    )]
+    stats: Vec<Statistic>,
+    /// Keep fields apart when aggregating
+    #[clap(long, action, requires = "aggregate")]
+    by_field: bool,
+    /// Keep timepoints apart when aggregating
+    #[clap(long, action, requires = "aggregate")]
+    by_timepoint: bool,
+    /// Add a well ID column (e.g. A01) built from the Row and Column data
+    #[clap(short, long, action)]
+    well: bool,
+    /// Name of the added well column
+    #[clap(long, value_parser, default_value = "Well", requires = "well")]
+    well_name: String,
+    /// Well ID style: padded (A01) or unpadded (A1)
+    #[clap(long, value_parser, default_value = "padded", requires = "well")]
+    well_style: WellStyle,
+    /// Plate format (96, 384 or 1536) to check well IDs against and to lay out plate matrices
+    /// and heatmaps in, instead of detecting it
+    #[clap(long, value_parser)]
+    plate_format: Option<PlateFormat>,
+    /// CSV plate map(s) with plate, well and annotation columns to join onto each row
+    #[clap(short, long, value_parser, value_name = "CSV")]
+    plate_map: Vec<PathBuf>,
+    /// Regex with named groups that splits plate names into extra columns
+    #[clap(long, value_parser, value_name = "REGEX")]
+    plate_pattern: Option<PlateNamePattern>,
+    /// File holding the plate name pattern, instead of the input directory's plate-pattern.txt
+    #[clap(
+        long,
+        value_parser,
+        value_name = "FILE",
+        conflicts_with = "plate-pattern"
+    )]
+    plate_pattern_file: Option<PathBuf>,
+    /// Name the directories below the input as extra columns, e.g. {experiment}/{condition}/**
+    #[clap(long, value_parser, value_name = "TEMPLATE")]
+    path_template: Option<String>,
+    /// Feature to normalise per plate against the plate map's control wells, can be repeated
+    #[clap(
+        long = "normalise",
+        value_parser,
+        value_name = "FEATURE",
+        requires = "plate-map",
+        conflicts_with = "separate"
+    )]
+    normalise: Vec<String>,
+    /// Normalisations to add: poc (percent of control), npi (percent inhibition), z, robust-z
+    #[clap(
+        long,
+        value_parser,
+        use_value_delimiter = true,
+        default_value = "poc",
+        requires = "normalise"
+    )]
+    norm_method: Vec<NormMethod>,
+    /// Normalise or B-score each timepoint of a plate on its own
+    #[clap(long, action)]
+    per_timepoint: bool,
+    /// Plate map column that marks control wells
+    #[clap(long, value_parser, default_value = "Control", requires = "plate-map")]
+    control_column: String,
+    /// Value marking negative control wells
+    #[clap(long, value_parser, default_value = "neg", requires = "plate-map")]
+    negative: String,
+    /// Value marking positive control wells
+    #[clap(long, value_parser, default_value = "pos", requires = "plate-map")]
+    positive: String,
+    /// Feature to check control quality (Z'-factor, SSMD, S/B, CV) of per plate, can be repeated
+    #[clap(
+        long = "qc",
+        value_parser,
+        value_name = "FEATURE",
+        requires_all = &["plate-map", "qc-report"],
+        conflicts_with = "separate"
+    )]
+    qc: Vec<String>,
+    /// File to write the per-plate QC table to
+    #[clap(long, value_parser, value_name = "FILE", requires = "qc")]
+    qc_report: Option<PathBuf>,
+    /// Flag plates with a lower Z'-factor
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = 0.5,
+        allow_hyphen_values = true,
+        requires = "qc"
+    )]
+    min_z_prime: f64,
+    /// Don't check the Z'-factor
+    #[clap(long, action, requires = "qc", conflicts_with = "min-z-prime")]
+    no_z_prime: bool,
+    /// Flag plates with a smaller absolute SSMD
+    #[clap(long, value_parser, requires = "qc")]
+    min_ssmd: Option<f64>,
+    /// Flag plates with a smaller signal window (S/B or B/S)
+    #[clap(long, value_parser, requires = "qc")]
+    min_signal_background: Option<f64>,
+    /// Flag plates with a larger control CV, in percent
+    #[clap(long, value_parser, requires = "qc")]
+    max_control_cv: Option<f64>,
+    /// Feature to correct for plate row and column effects with a B-score, can be repeated
+    #[clap(
+        long = "b-score",
+        value_parser,
+        value_name = "FEATURE",
+        conflicts_with = "separate"
+    )]
+    b_score: Vec<String>,
+    /// Feature to fit 4PL dose-response curves to per compound, can be repeated
+    #[clap(
+        long = "dose-response",
+        value_parser,
+        value_name = "FEATURE",
+        requires_all = &["plate-map", "dose-response-report"],
+        conflicts_with = "separate"
+    )]
+    dose_response: Vec<String>,
+    /// File to write the dose-response fits to
+    #[clap(long, value_parser, value_name = "FILE", requires = "dose-response")]
+    dose_response_report: Option<PathBuf>,
+    /// Plate map column naming the compound in each well
+    #[clap(
+        long,
+        value_parser,
+        default_value = "Compound",
+        requires = "dose-response"
+    )]
+    compound_column: String,
+    /// Plate map column with the concentration in each well
+    #[clap(
+        long,
+        value_parser,
+        default_value = "Concentration",
+        requires = "dose-response"
+    )]
+    concentration_column: String,
+    /// Feature to compare between replicate plates, can be repeated
+    #[clap(
+        long = "concordance",
+        value_parser,
+        value_name = "FEATURE",
+        requires = "concordance-report",
+        conflicts_with = "separate"
+    )]
+    concordance: Vec<String>,
+    /// File to write replicate correlations and Bland-Altman summaries to
+    #[clap(long, value_parser, value_name = "FILE", requires = "concordance")]
+    concordance_report: Option<PathBuf>,
+    /// File to write wells whose replicates disagree to
+    #[clap(long, value_parser, value_name = "FILE", requires = "concordance")]
+    flagged_wells: Option<PathBuf>,
+    /// Regex matching the part of plate names that differs between replicates [default: _rep\d+]
+    #[clap(long, value_parser, value_name = "REGEX", requires = "concordance")]
+    replicate_pattern: Option<String>,
+    /// Column whose value groups replicate plates, instead of a plate name pattern
+    #[clap(
+        long,
+        value_parser,
+        value_name = "NAME",
+        requires = "concordance",
+        conflicts_with = "replicate-pattern"
+    )]
+    replicate_column: Option<String>,
+    /// Flag wells whose replicate difference is this many SDs from the plates' mean difference
+    #[clap(long, value_parser, default_value_t = 1.96, requires = "concordance")]
+    max_disagreement: f64,
+    /// Order the measurements and timepoints of each plate into a time course
+    #[clap(long, action, conflicts_with = "separate")]
+    time_course: bool,
+    /// Time between timepoints, e.g. 30m or 2h
+    #[clap(
+        long,
+        value_parser = harmony::parse_interval,
+        value_name = "DURATION",
+        requires = "time-course"
+    )]
+    interval: Option<Duration>,
+    /// Data column with acquisition times, used when no interval is given
+    #[clap(
+        long,
+        value_parser,
+        default_value = "Time [s]",
+        requires = "time-course",
+        conflicts_with = "interval"
+    )]
+    time_column: String,
+    /// Feature to spread into one column per timepoint, giving one row per well; can be repeated
+    #[clap(
+        long = "time-course-wide",
+        value_parser,
+        value_name = "FEATURE",
+        requires = "time-course"
+    )]
+    time_course_wide: Vec<String>,
+    /// Feature to lay out as a plate-shaped grid per plate, can be repeated
+    #[clap(
+        long = "matrix",
+        value_parser,
+        value_name = "FEATURE",
+        requires = "matrix-output",
+        conflicts_with = "separate"
+    )]
+    matrix: Vec<String>,
+    /// File to write the plate grids to, or the directory for --matrix-files
+    #[clap(long, value_parser, value_name = "PATH", requires = "matrix")]
+    matrix_output: Option<PathBuf>,
+    /// Write each plate grid to its own file in the matrix output directory
+    #[clap(long, action, requires = "matrix")]
+    matrix_files: bool,
+    /// Feature to draw as a heatmap image per plate, can be repeated
+    #[clap(
+        long = "heatmap",
+        value_parser,
+        value_name = "FEATURE",
+        requires = "heatmap-dir",
+        conflicts_with = "separate"
+    )]
+    heatmap: Vec<String>,
+    /// Directory to write the heatmaps into, one file per plate and feature
+    #[clap(long, value_parser, value_name = "DIR", requires = "heatmap")]
+    heatmap_dir: Option<PathBuf>,
+    /// Heatmap image type: png or svg
+    #[clap(long, value_parser, default_value = "png", requires = "heatmap")]
+    heatmap_format: ImageFormat,
+    /// Heatmap colour scale range, either `plate` for each plate's own range or `MIN,MAX`
+    #[clap(
+        long,
+        value_parser,
+        default_value = "plate",
+        allow_hyphen_values = true
+    )]
+    heatmap_range: ColourRange,
+    /// Write an HTML summary of the files, plates and columns combined
+    #[clap(long, value_parser, value_name = "HTML", conflicts_with = "separate")]
+    report: Option<PathBuf>,
+    /// Feature to include as per-plate heatmaps in the report, can be repeated
+    #[clap(
+        long = "report-heatmap",
+        value_parser,
+        value_name = "FEATURE",
+        requires = "report"
+    )]
+    report_heatmap: Vec<String>,
+    /// Leave plates flagged by QC out of the output
+    #[clap(long, action, requires = "qc")]
+    drop_failed_qc: bool,
+}
+
+/// File type of the combined output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Tsv,
+    Sqlite,
+    Xlsx,
+    Jsonl,
+    Arrow,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tsv" => Ok(Self::Tsv),
+            "sqlite" | "db" => Ok(Self::Sqlite),
+            "xlsx" | "excel" => Ok(Self::Xlsx),
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            "arrow" | "ipc" => Ok(Self::Arrow),
+            _ => Err(format!(
+                "unknown output format <{s}>, expected tsv, sqlite, xlsx, jsonl or arrow"
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// File extension of separate population files
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Tsv => "tsv",
+            Self::Sqlite => "db",
+            Self::Xlsx => "xlsx",
+            Self::Jsonl => "jsonl",
+            Self::Arrow => "arrow",
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write a blank CSV plate map for the plates and wells found in the harmony files
+    Template {
+        /// directory to search for harmony files
+        #[clap(value_parser)]
+        input: PathBuf,
+        /// output CSV file name, or stdout if not present
+        #[clap(value_parser)]
+        output: Option<PathBuf>,
+        /// annotation column to include, can be repeated
+        #[clap(short, long = "column", value_parser, value_name = "NAME")]
+        columns: Vec<String>,
+    },
+    /// List the harmony files found below a directory, and the text files passed over
+    Scan {
+        /// directory to search for harmony files
+        #[clap(value_parser)]
+        input: PathBuf,
+        /// output file name, or stdout if not present
+        #[clap(value_parser)]
+        output: Option<PathBuf>,
+        /// Write the scan as versioned JSON, to keep or compare between runs
+        #[clap(long, action)]
+        json: bool,
+    },
+}
+
+impl Args {
+    fn combine_options(&self) -> Result<CombineOptions> {
+        let plate_map = if self.plate_map.is_empty() {
+            None
+        } else {
+            let map = PlateMap::from_files(&self.plate_map).context("reading plate maps")?;
+            Some(map)
+        };
+        let plate_pattern = match (&self.plate_pattern, &self.plate_pattern_file) {
+            (Some(pat), _) => Some(pat.clone()),
+            (None, Some(p)) => Some(read_plate_pattern(p)?),
+            (None, None) => {
+                // fall back to the project's pattern file in the search directory
+                let p = self
+                    .input
+                    .as_deref()
+                    .map(|dir| dir.join(harmony::PLATE_PATTERN_FILE))
+                    .filter(|p| p.is_file());
+                match p {
+                    Some(p) => {
+                        eprintln!("using plate name pattern from {}", p.display());
+                        Some(read_plate_pattern(&p)?)
+                    }
+                    None => None,
+                }
+            }
+        };
+
+        let path_template = match (&self.path_template, &self.input) {
+            (Some(t), Some(root)) => Some(PathTemplate::new(root, t).map_err(anyhow::Error::msg)?),
+            _ => None,
+        };
+
+        Ok(CombineOptions {
+            well: self.well.then(|| WellOptions {
+                name: self.well_name.clone(),
+                style: self.well_style,
+                format: self.plate_format,
+            }),
+            plate_map,
+            plate_pattern,
+            path_template,
+            layout: match (self.wide, self.melt) {
+                (true, _) => Layout::Wide,
+                (_, true) => Layout::Long,
+                _ => Layout::Stacked,
+            },
+            aggregate: self.aggregate.then(|| AggregateOptions {
+                features: self.features.clone(),
+                statistics: self.stats.clone(),
+                by_field: self.by_field,
+                by_timepoint: self.by_timepoint,
+            }),
+        })
+    }
+
+    fn report_step(&self) -> Option<ReportStep> {
+        self.report.clone().map(|path| ReportStep {
+            path,
+            features: self.report_heatmap.clone(),
+            format: self.plate_format,
+            range: self.heatmap_range,
+        })
+    }
+
+    fn table_steps(&self) -> Result<TableSteps> {
+        let controls = Controls {
+            column: self.control_column.clone(),
+            negative: self.negative.clone(),
+            positive: self.positive.clone(),
+        };
+        let concordance = match &self.concordance_report {
+            Some(report) => {
+                let grouping = match &self.replicate_column {
+                    Some(c) => ReplicateGrouping::Column(c.clone()),
+                    None => {
+                        let pattern = self.replicate_pattern.as_deref().unwrap_or(r"_rep\d+");
+                        ReplicateGrouping::pattern(pattern).map_err(anyhow::Error::msg)?
+                    }
+                };
+                let opts = ConcordanceOptions {
+                    features: self.concordance.clone(),
+                    grouping,
+                    max_disagreement: self.max_disagreement,
+                };
+                Some((opts, report.clone(), self.flagged_wells.clone()))
+            }
+            None => None,
+        };
+
+        Ok(TableSteps {
+            b_score: (!self.b_score.is_empty()).then(|| BScoreOptions {
+                features: self.b_score.clone(),
+                per_timepoint: self.per_timepoint,
+                ..BScoreOptions::default()
+            }),
+            normalise: (!self.normalise.is_empty()).then(|| NormaliseOptions {
+                features: self.normalise.clone(),
+                methods: self.norm_method.clone(),
+                controls: controls.clone(),
+                per_timepoint: self.per_timepoint,
+            }),
+            qc: self.qc_report.clone().map(|report| QcStep {
+                opts: QcOptions {
+                    features: self.qc.clone(),
+                    controls,
+                    thresholds: QcThresholds {
+                        min_z_prime: (!self.no_z_prime).then_some(self.min_z_prime),
+                        min_ssmd: self.min_ssmd,
+                        min_signal_background: self.min_signal_background,
+                        max_control_cv: self.max_control_cv,
+                    },
+                },
+                report,
+                drop_failed: self.drop_failed_qc,
+            }),
+            dose_response: self.dose_response_report.clone().map(|report| {
+                let opts = DoseResponseOptions {
+                    features: self.dose_response.clone(),
+                    compound_column: self.compound_column.clone(),
+                    concentration_column: self.concentration_column.clone(),
+                };
+                (opts, report)
+            }),
+            concordance,
+            matrix: self.matrix_output.clone().map(|output| MatrixStep {
+                features: self.matrix.clone(),
+                format: self.plate_format,
+                output,
+                separate: self.matrix_files,
+            }),
+            heatmap: self.heatmap_dir.clone().map(|dir| HeatmapStep {
+                features: self.heatmap.clone(),
+                format: self.plate_format,
+                dir,
+                opts: HeatmapOptions {
+                    format: self.heatmap_format,
+                    range: self.heatmap_range,
+                },
+            }),
+            time_course: self.time_course.then(|| TimeCourseOptions {
+                time: match self.interval {
+                    Some(d) => TimeSource::Interval(d),
+                    None => TimeSource::Column(self.time_column.clone()),
+                },
+                wide_features: self.time_course_wide.clone(),
+            }),
+        })
+    }
+}
+
+/// Steps that need every row of a plate, run between combining and writing the output
+struct TableSteps {
+    b_score: Option<BScoreOptions>,
+    normalise: Option<NormaliseOptions>,
+    qc: Option<QcStep>,
+    /// fits are written to their own file
+    dose_response: Option<(DoseResponseOptions, PathBuf)>,
+    /// with the pairs report and the optional flagged wells file
+    concordance: Option<(ConcordanceOptions, PathBuf, Option<PathBuf>)>,
+    matrix: Option<MatrixStep>,
+    heatmap: Option<HeatmapStep>,
+    time_course: Option<TimeCourseOptions>,
+}
+
+struct MatrixStep {
+    features: Vec<String>,
+    format: Option<PlateFormat>,
+    output: PathBuf,
+    /// `output` is a directory to write a file per grid into
+    separate: bool,
+}
+
+struct HeatmapStep {
+    features: Vec<String>,
+    format: Option<PlateFormat>,
+    dir: PathBuf,
+    opts: HeatmapOptions,
+}
+
+/// The HTML run report, and the features to draw for it
+struct ReportStep {
+    path: PathBuf,
+    features: Vec<String>,
+    format: Option<PlateFormat>,
+    range: ColourRange,
+}
+
+struct QcStep {
+    opts: QcOptions,
+    report: PathBuf,
+    drop_failed: bool,
+}
+
+impl TableSteps {
+    fn is_empty(&self) -> bool {
+        self.b_score.is_none()
+            && self.normalise.is_none()
+            && self.qc.is_none()
+            && self.dose_response.is_none()
+            && self.concordance.is_none()
+            && self.matrix.is_none()
+            && self.heatmap.is_none()
+            && self.time_course.is_none()
+    }
+
+    fn run(&self, table: &mut Table, map: Option<&PlateMap>) -> Result<()> {
+        // QC looks at the raw values, before any corrected columns are added
+        if let (Some(qc), Some(map)) = (&self.qc, map) {
+            let report = harmony::plate_qc(table, map, &qc.opts).context("checking plate QC")?;
+            let wtr = create_bufwriter(&qc.report)?;
+            report.write_tsv(wtr).context("writing QC report")?;
+            let failed = report.failed_groups();
+            for group in &failed {
+                let (plate, rest) = group.split_first().expect("groups start with the plate");
+                let others = report.group_columns[1..]
+                    .iter()
+                    .zip(rest)
+                    .map(|(c, v)| format!(", {c} {v}"))
+                    .collect::<String>();
+                eprintln!("plate <{}>{} failed QC", plate, others);
+            }
+            if qc.drop_failed {
+                table.retain_plates(|key| !failed.contains(key));
+            }
+        }
+        if let Some(bscore) = &self.b_score {
+            harmony::b_score(table, bscore).context("computing B-scores")?;
+        }
+        if let (Some(norm), Some(map)) = (&self.normalise, map) {
+            let report = harmony::normalise(table, map, norm).context("normalising")?;
+            print_norm_report(&report);
+        }
+        if let Some((opts, path, flagged)) = &self.concordance {
+            let report = harmony::replicate_concordance(table, opts)
+                .context("comparing replicate plates")?;
+            report
+                .write_pairs_tsv(create_bufwriter(path)?)
+                .context("writing concordance report")?;
+            if let Some(p) = flagged {
+                report
+                    .write_flagged_tsv(create_bufwriter(p)?)
+                    .context("writing flagged wells")?;
+            }
+            for plate in &report.unpaired {
+                eprintln!("plate <{}> has no replicate", plate);
+            }
+        }
+        // after normalising, so curves can be fit to normalised columns
+        if let Some((opts, path)) = &self.dose_response {
+            let fits = harmony::fit_dose_response(table, opts).context("fitting dose-response")?;
+            let wtr = create_bufwriter(path)?;
+            harmony::write_dose_response(wtr, &fits).context("writing dose-response fits")?;
+        }
+        if let Some(step) = &self.matrix {
+            let matrices = harmony::plate_matrices(table, &step.features, step.format)
+                .context("laying out plate matrices")?;
+            if step.separate {
+                std::fs::create_dir_all(&step.output).with_context(|| {
+                    format!("creating matrix directory {}", step.output.display())
+                })?;
+                for m in &matrices {
+                    let p = step.output.join(format!("{}.tsv", m.file_stem()));
+                    m.write_grid(create_bufwriter(p)?)
+                        .context("writing plate matrix")?;
+                }
+            } else {
+                let wtr = create_bufwriter(&step.output)?;
+                harmony::write_plate_matrices(wtr, &matrices).context("writing plate matrices")?;
+            }
+        }
+        if let Some(step) = &self.heatmap {
+            let matrices = harmony::plate_matrices(table, &step.features, step.format)
+                .context("laying out plate heatmaps")?;
+            let written = harmony::write_heatmaps(&step.dir, &matrices, &step.opts)
+                .with_context(|| format!("writing heatmaps to {}", step.dir.display()))?;
+            eprintln!("wrote {} heatmaps to {}", written.len(), step.dir.display());
+        }
+        // last, since it reorders the rows and can reshape the table
+        if let Some(opts) = &self.time_course {
+            harmony::time_course(table, opts).context("assembling time course")?;
+        }
+        Ok(())
+    }
+}
+
+fn read_plate_pattern(p: &Path) -> Result<PlateNamePattern> {
+    PlateNamePattern::from_file(p)
+        .with_context(|| format!("reading plate name pattern {}", p.display()))
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
+    if let Some(cmd) = &args.command {
+        return match cmd {
+            Command::Template {
+                input,
+                output,
+                columns,
+            } => write_template(input, output.as_deref(), columns),
+            Command::Scan {
+                input,
+                output,
+                json,
+            } => write_scan(input, output.as_deref(), *json),
+        };
+    }
+
+    let opts = args.combine_options()?;
+    let steps = args.table_steps()?;
+    let report = args.report_step();
+    let input = args
+        .input
+        .as_deref()
+        .expect("input is required without a subcommand");
+
+    if args.dry_run {
+        return print_plan(input, &opts);
+    }
+
+    if matches!(args.format, OutputFormat::Sqlite | OutputFormat::Xlsx) {
+        let reshaped = opts.layout != Layout::Stacked || opts.aggregate.is_some();
+        if args.separate || reshaped || !steps.is_empty() || report.is_some() {
+            anyhow::bail!(
+                "sqlite and xlsx output can't be separated, reshaped, aggregated or analysed; \
+                 they write every row as it is"
+            );
+        }
+        return write_records(input, args.output.as_deref(), &opts, args.format);
+    }
+
     match (args.separate, args.output.as_deref()) {
-        (true, Some(out)) => separate_by_pop(&args.input, out),
-        _ => combine_files(&args.input, args.output.as_deref()),
+        (true, Some(out)) => separate_by_pop(input, out, &opts, args.format),
+        _ => combine_files(
+            input,
+            args.output.as_deref(),
+            &opts,
+            &steps,
+            report.as_ref(),
+            args.format,
+        ),
+    }
+}
+
+fn combine_files(
+    dir: &Path,
+    out: Option<&Path>,
+    opts: &CombineOptions,
+    steps: &TableSteps,
+    run_report: Option<&ReportStep>,
+    format: OutputFormat,
+) -> Result<()> {
+    let mut stdout;
+    let mut fbuf;
+
+    let wtr = if let Some(p) = out {
+        fbuf = create_bufwriter(p)?;
+        &mut fbuf as &mut dyn Write
+    } else {
+        stdout = std::io::stdout().lock();
+        &mut stdout as &mut dyn Write
+    };
+    let mut sink = record_sink(format, wtr);
+
+    let scan = harmony::scan_harmony_datafiles(dir);
+    let metadata = &scan.files;
+    if metadata.is_empty() {
+        anyhow::bail!("did not find any harmony files");
+    }
+    let report_features = run_report.map_or(&[][..], |r| &r.features);
+    let (report, heatmaps) = if steps.is_empty() && report_features.is_empty() {
+        let report =
+            harmony::combine_into(&mut *sink, metadata, opts).context("combining files")?;
+        (report, Vec::new())
+    } else {
+        // these steps need every row of a plate before writing any of them
+        let (mut table, report) =
+            harmony::combine_table(metadata, opts).context("combining files")?;
+        steps.run(&mut table, opts.plate_map.as_ref())?;
+        let format = run_report.and_then(|r| r.format);
+        let heatmaps = harmony::plate_matrices(&table, report_features, format)
+            .context("laying out report heatmaps")?;
+        table.write_to(&mut *sink).context("writing output")?;
+        (report, heatmaps)
+    };
+    print_report(&report);
+
+    if let Some(r) = run_report {
+        let html = harmony::RunReport {
+            scan: &scan,
+            combine: &report,
+            heatmaps: &heatmaps,
+            heatmap_range: r.range,
+        };
+        html.write_html(create_bufwriter(&r.path)?)
+            .context("writing run report")?;
+    }
+    Ok(())
+}
+
+/// Write every row to a database or workbook
+fn write_records(
+    dir: &Path,
+    out: Option<&Path>,
+    opts: &CombineOptions,
+    format: OutputFormat,
+) -> Result<()> {
+    let metadata = harmony::collect_harmony_datafiles(dir);
+    if metadata.is_empty() {
+        anyhow::bail!("did not find any harmony files");
+    }
+    let out = out.context("database and workbook formats need an output file")?;
+    let report = match format {
+        OutputFormat::Sqlite => harmony::write_sqlite(out, &metadata, opts),
+        OutputFormat::Xlsx => harmony::write_xlsx(out, &metadata, opts),
+        _ => unreachable!("streamed formats are written by combine_files"),
+    }
+    .with_context(|| format!("writing {}", out.display()))?;
+    print_report(&report);
+    Ok(())
+}
+
+/// Sink writing the combined rows in one of the streamed formats
+fn record_sink<'a>(format: OutputFormat, wtr: &'a mut dyn Write) -> Box<dyn RecordSink + 'a> {
+    match format {
+        OutputFormat::Jsonl => Box::new(JsonlSink::new(wtr)),
+        OutputFormat::Arrow => Box::new(ArrowSink::new(wtr)),
+        _ => Box::new(TsvSink::new(wtr)),
+    }
+}
+
+fn print_plan(dir: &Path, opts: &CombineOptions) -> Result<()> {
+    let metadata = harmony::collect_harmony_datafiles(dir);
+    if metadata.is_empty() {
+        anyhow::bail!("did not find any harmony files");
+    }
+    let plan = harmony::plan_combine(&metadata, opts).context("planning the combined output")?;
+    let n = plan.files.len();
+    let width = plan
+        .header
+        .iter()
+        .map(|h| h.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    println!("{} columns:", plan.header.len());
+    let derived = plan.header.len() - plan.columns.len();
+    for h in &plan.header[..derived] {
+        println!("  {h:width$}  metadata");
+    }
+    for c in &plan.columns {
+        if c.files.len() == n {
+            println!("  {:width$}  all files", c.name);
+        } else {
+            println!("  {:width$}  {} of {n} files", c.name, c.files.len());
+        }
+    }
+
+    println!("{n} files, {} rows:", plan.rows());
+    for f in &plan.files {
+        let missing = if f.missing.is_empty() {
+            String::new()
+        } else {
+            let names = f.missing.iter().map(|m| m.as_ref()).collect::<Vec<_>>();
+            format!(", lacks {}", names.join(", "))
+        };
+        println!("  {}: {} rows{missing}", f.path.display(), f.rows);
     }
+    Ok(())
 }
 
-fn combine_files(dir: &Path, out: Option<&Path>) -> Result<()> {
+fn write_template(dir: &Path, out: Option<&Path>, columns: &[String]) -> Result<()> {
     let mut stdout;
     let mut fbuf;
 
@@ -47,11 +882,61 @@ fn combine_files(dir: &Path, out: Option<&Path>) -> Result<()> {
     if metadata.is_empty() {
         anyhow::bail!("did not find any harmony files");
     }
-    harmony::combine_files(wtr, &metadata).context("combining files")?;
+    if columns.is_empty() {
+        harmony::write_plate_map_template(wtr, &metadata, harmony::DEFAULT_TEMPLATE_COLUMNS)
+    } else {
+        harmony::write_plate_map_template(wtr, &metadata, columns)
+    }
+    .context("writing plate map template")
+}
+
+fn write_scan(dir: &Path, out: Option<&Path>, json: bool) -> Result<()> {
+    let mut stdout;
+    let mut fbuf;
+
+    let wtr = if let Some(p) = out {
+        fbuf = create_bufwriter(p)?;
+        &mut fbuf as &mut dyn Write
+    } else {
+        stdout = std::io::stdout().lock();
+        &mut stdout as &mut dyn Write
+    };
+
+    let scan = harmony::scan_harmony_datafiles(dir);
+    if json {
+        scan.write_json(&mut *wtr)
+            .and_then(|_| writeln!(wtr))
+            .context("writing scan")?;
+    } else {
+        writeln!(
+            wtr,
+            "Path\tPlate Name\tMeasurement\tEvaluation\tPopulation\tColumns"
+        )?;
+        for m in &scan.files {
+            writeln!(
+                wtr,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                m.path.display(),
+                m.plate_name,
+                m.measurement,
+                m.evaluation,
+                m.population.as_deref().unwrap_or_default(),
+                m.headers.len()
+            )?;
+        }
+        for p in &scan.skipped {
+            eprintln!("skipped {}: not a harmony export", p.display());
+        }
+    }
     Ok(())
 }
 
-fn separate_by_pop(dir: &Path, out: &Path) -> Result<()> {
+fn separate_by_pop(
+    dir: &Path,
+    out: &Path,
+    opts: &CombineOptions,
+    format: OutputFormat,
+) -> Result<()> {
     let pops = harmony::iterate_harmony_datafiles(dir).fold(HashMap::new(), |mut map, md| {
         let pop = md.population.clone();
         map.entry(pop).or_insert_with(Vec::new).push(md);
@@ -73,15 +958,40 @@ fn separate_by_pop(dir: &Path, out: &Path) -> Result<()> {
         .iter()
         .map(|(k, v)| (k.as_deref().unwrap_or("WellData"), v));
     for (pop, metadata) in iter {
-        let p = out.with_file_name(format!("{}_{}.tsv", basename, pop));
-        let wtr = create_bufwriter(p)?;
-        harmony::combine_files(wtr, &metadata)
+        let p = out.with_file_name(format!("{}_{}.{}", basename, pop, format.extension()));
+        let mut wtr = create_bufwriter(p)?;
+        let report = harmony::combine_into(&mut *record_sink(format, &mut wtr), metadata, opts)
             .with_context(|| format!("combining population: {}", pop))?;
+        print_report(&report);
     }
 
     Ok(())
 }
 
+fn print_report(report: &CombineReport) {
+    for w in report.warnings() {
+        eprintln!("{w}");
+    }
+}
+
+fn print_norm_report(report: &NormaliseReport) {
+    let missing = [
+        ("negative", &report.missing_negative),
+        ("positive", &report.missing_positive),
+    ];
+    for (kind, groups) in missing {
+        for (plate, timepoint) in groups {
+            match timepoint {
+                Some(t) => eprintln!(
+                    "no {} control wells on plate <{}> at timepoint {}",
+                    kind, plate, t
+                ),
+                None => eprintln!("no {} control wells on plate <{}>", kind, plate),
+            }
+        }
+    }
+}
+
 fn create_bufwriter<P: AsRef<Path>>(p: P) -> Result<BufWriter<File>> {
     let p = p.as_ref();
     File::create(p)
@@ -0,0 +1,419 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{
+    info::HarmonyMetadata,
+    rows::{Row, Rows},
+    sink::RecordSink,
+    stats::{self, parse_value},
+    utils::column_index,
+    well::{COLUMN_HDR, ROW_HDR},
+    write::{CombineOptions, CombineReport, MetaColumns},
+};
+
+const FIELD_HDR: &str = "Field";
+const TIMEPOINT_HDR: &str = "Timepoint";
+/// Columns that identify objects or positions rather than measure them,
+/// left out when no features are chosen and kept as keys in the long layout
+pub(crate) const ID_HDRS: &[&str] = &[
+    ROW_HDR,
+    COLUMN_HDR,
+    FIELD_HDR,
+    TIMEPOINT_HDR,
+    "Plane",
+    "Object No",
+];
+
+/// Summarise object-level rows into one row per well
+#[derive(Debug, Clone)]
+pub struct AggregateOptions {
+    /// numeric columns to summarise, or every non-ID column when empty
+    pub features: Vec<String>,
+    pub statistics: Vec<Statistic>,
+    /// keep fields apart instead of pooling them per well
+    pub by_field: bool,
+    /// keep timepoints apart instead of pooling them per well
+    pub by_timepoint: bool,
+}
+
+impl Default for AggregateOptions {
+    fn default() -> Self {
+        Self {
+            features: Vec::new(),
+            statistics: vec![
+                Statistic::Count,
+                Statistic::Mean,
+                Statistic::Median,
+                Statistic::StdDev,
+                Statistic::Mad,
+            ],
+            by_field: false,
+            by_timepoint: false,
+        }
+    }
+}
+
+/// Summary statistic of a feature's values in a well
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+    /// number of numeric values
+    Count,
+    Mean,
+    Median,
+    /// sample standard deviation
+    StdDev,
+    /// unscaled median absolute deviation
+    Mad,
+    /// percentile between 0 and 100, interpolated between the closest values
+    Quantile(f64),
+}
+
+impl Statistic {
+    fn compute(self, sorted: &[f64]) -> f64 {
+        match self {
+            Self::Count => sorted.len() as f64,
+            Self::Mean => stats::mean(sorted),
+            Self::Median => stats::quantile_sorted(sorted, 0.5),
+            Self::StdDev => stats::std_dev(sorted),
+            Self::Mad => stats::mad(sorted),
+            Self::Quantile(p) => stats::quantile_sorted(sorted, p / 100.0),
+        }
+    }
+}
+
+impl fmt::Display for Statistic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Count => write!(f, "Count"),
+            Self::Mean => write!(f, "Mean"),
+            Self::Median => write!(f, "Median"),
+            Self::StdDev => write!(f, "SD"),
+            Self::Mad => write!(f, "MAD"),
+            Self::Quantile(p) => write!(f, "Q{p}"),
+        }
+    }
+}
+
+impl FromStr for Statistic {
+    type Err = String;
+
+    /// Parse `count`, `mean`, `median`, `sd`, `mad`, or a percentile such as `q25` or `q2.5`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_ascii_lowercase();
+        match lower.as_str() {
+            "count" => Ok(Self::Count),
+            "mean" => Ok(Self::Mean),
+            "median" => Ok(Self::Median),
+            "sd" | "stddev" => Ok(Self::StdDev),
+            "mad" => Ok(Self::Mad),
+            _ => lower
+                .strip_prefix('q')
+                .and_then(|p| p.parse::<f64>().ok())
+                .filter(|p| (0.0..=100.0).contains(p))
+                .map(Self::Quantile)
+                .ok_or_else(|| {
+                    format!("unknown statistic <{s}>, expected count, mean, median, sd, mad or q<percentile>")
+                }),
+        }
+    }
+}
+
+/// Write one row per well (and field/timepoint if asked) with summaries of each feature.
+///
+/// Files are read one at a time, and only the chosen features are kept, as numbers.
+/// Harmony writes the rows of a well together, so each group is summarised and its
+/// values dropped as soon as the next group starts, leaving one group's values and the
+/// file's summary rows in memory. A file whose groups are interleaved, where a key
+/// comes back after another group, is read again with the values of all its groups
+/// held until the end of the file.
+pub(crate) fn write_aggregated(
+    sink: &mut impl RecordSink,
+    md: &[HarmonyMetadata],
+    opts: &CombineOptions,
+    agg: &AggregateOptions,
+) -> io::Result<CombineReport> {
+    let mut report = CombineReport::default();
+
+    let mut keys = vec![ROW_HDR, COLUMN_HDR];
+    if agg.by_field {
+        keys.push(FIELD_HDR);
+    }
+    if agg.by_timepoint {
+        keys.push(TIMEPOINT_HDR);
+    }
+    let key_hdr = keys
+        .iter()
+        .map(|&k| Arc::from(k))
+        .collect::<Vec<Arc<str>>>();
+    let meta = MetaColumns::new(md, opts, &key_hdr)?;
+
+    let features: Vec<Arc<str>> = if agg.features.is_empty() {
+        let mut features: Vec<Arc<str>> = Vec::new();
+        for h in md.iter().flat_map(|m| m.headers.iter()) {
+            if !ID_HDRS.contains(&h.as_ref()) && !features.contains(h) {
+                features.push(Arc::clone(h));
+            }
+        }
+        features
+    } else {
+        agg.features.iter().map(|f| Arc::from(f.as_str())).collect()
+    };
+
+    let summaries = features
+        .iter()
+        .flat_map(|f| agg.statistics.iter().map(move |s| format!("{f} - {s}")))
+        .collect::<Vec<_>>();
+    let mut header = meta.header(true);
+    header.extend(&keys);
+    header.extend(summaries.iter().map(String::as_str));
+    sink.begin_schema(&header)?;
+
+    let columns = key_hdr.iter().chain(&features).cloned().collect::<Vec<_>>();
+    for m in md {
+        if keys.iter().any(|k| column_index(&m.headers, k).is_none()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is missing one of the {} columns to group by",
+                    m.path.display(),
+                    keys.join("/")
+                ),
+            ));
+        }
+        let common_info = meta.file_values(m, true, &mut report);
+
+        let (groups, n) = match summarise_grouped(m, &columns, keys.len(), agg)? {
+            Some(grouped) => grouped,
+            None => summarise_interleaved(m, &columns, keys.len(), agg)?,
+        };
+        report.add_rows(m, n);
+        for (key, summaries) in groups {
+            let key_fields = key.iter().map(String::as_str).collect::<Vec<_>>();
+            let well = meta.well(&key_fields);
+
+            let well_values = meta.well_values(&m.plate_name, well, &mut report);
+            let row = common_info
+                .iter()
+                .chain(&well_values)
+                .chain(&key)
+                .chain(&summaries)
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            sink.write_row(&row)?;
+        }
+        sink.end_file(m)?;
+    }
+
+    Ok(report)
+}
+
+/// Key and summaries of each group of a file, in order of first appearance, and the
+/// number of rows summarised
+type Summaries = (Vec<(Vec<String>, Vec<String>)>, usize);
+
+/// Summarise a file whose rows are grouped by key, holding one group's values at a
+/// time, or return `None` if a key comes back after another group
+fn summarise_grouped(
+    m: &HarmonyMetadata,
+    columns: &[Arc<str>],
+    n_keys: usize,
+    agg: &AggregateOptions,
+) -> io::Result<Option<Summaries>> {
+    let n_features = columns.len() - n_keys;
+    let mut groups = Vec::new();
+    let mut done = HashSet::new();
+    let mut current: Option<(Vec<String>, Vec<Vec<f64>>)> = None;
+    let mut n = 0;
+
+    let mut rows = Rows::with_header([m], columns.to_vec());
+    while let Some(row) = rows.next_row() {
+        let row = row?;
+        n += 1;
+        let same = matches!(&current, Some((key, _)) if (0..n_keys).all(|i| key[i] == row.cell(i)));
+        if !same {
+            let key = (0..n_keys)
+                .map(|i| row.cell(i).to_string())
+                .collect::<Vec<_>>();
+            if done.contains(&key) {
+                return Ok(None);
+            }
+            if let Some((key, mut values)) = current.take() {
+                groups.push((key.clone(), summarise(&mut values, agg)));
+                done.insert(key);
+            }
+            current = Some((key, vec![Vec::new(); n_features]));
+        }
+        if let Some((_, values)) = &mut current {
+            add_values(values, &row, n_keys);
+        }
+    }
+    if let Some((key, mut values)) = current {
+        groups.push((key, summarise(&mut values, agg)));
+    }
+    Ok(Some((groups, n)))
+}
+
+/// Summarise a file with the values of every group held until the end of the file
+fn summarise_interleaved(
+    m: &HarmonyMetadata,
+    columns: &[Arc<str>],
+    n_keys: usize,
+    agg: &AggregateOptions,
+) -> io::Result<Summaries> {
+    let n_features = columns.len() - n_keys;
+    let mut groups: Vec<(Vec<String>, Vec<Vec<f64>>)> = Vec::new();
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut n = 0;
+
+    let mut rows = Rows::with_header([m], columns.to_vec());
+    while let Some(row) = rows.next_row() {
+        let row = row?;
+        n += 1;
+        let key = (0..n_keys)
+            .map(|i| row.cell(i).to_string())
+            .collect::<Vec<_>>();
+        let group = *index.entry(key).or_insert_with_key(|key| {
+            groups.push((key.clone(), vec![Vec::new(); n_features]));
+            groups.len() - 1
+        });
+        add_values(&mut groups[group].1, &row, n_keys);
+    }
+
+    let groups = groups
+        .into_iter()
+        .map(|(key, mut values)| (key, summarise(&mut values, agg)))
+        .collect();
+    Ok((groups, n))
+}
+
+/// Add the numeric feature cells of a row to the values of its group
+fn add_values(values: &mut [Vec<f64>], row: &Row, n_keys: usize) {
+    for (i, vals) in values.iter_mut().enumerate() {
+        if let Some(v) = row.get(n_keys + i).and_then(parse_value) {
+            vals.push(v);
+        }
+    }
+}
+
+/// Every statistic of every feature, with missing results left empty
+fn summarise(values: &mut [Vec<f64>], agg: &AggregateOptions) -> Vec<String> {
+    let mut summaries = Vec::with_capacity(values.len() * agg.statistics.len());
+    for vals in values {
+        stats::sort(vals);
+        for s in &agg.statistics {
+            let v = s.compute(vals);
+            summaries.push(if v.is_nan() {
+                String::new()
+            } else {
+                v.to_string()
+            });
+        }
+    }
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_data::{cells, utf8, Datafile, TestDir},
+        write::combine_files_with,
+    };
+
+    #[test]
+    fn statistic_names() {
+        let parsed = ["Count", "mean", "SD", "stddev", "q7", "Q2.5", "q100"]
+            .map(|s| s.parse::<Statistic>().unwrap().to_string());
+        assert_eq!(parsed, ["Count", "Mean", "SD", "SD", "Q7", "Q2.5", "Q100"]);
+        assert!("q101".parse::<Statistic>().is_err());
+        assert!("max".parse::<Statistic>().is_err());
+    }
+
+    fn aggregate(dir: &TestDir, agg: AggregateOptions) -> (String, CombineReport) {
+        let opts = CombineOptions {
+            aggregate: Some(agg),
+            ..CombineOptions::default()
+        };
+        let mut out = Vec::new();
+        let report = combine_files_with(&mut out, &dir.metadata(), &opts).unwrap();
+        (utf8(out), report)
+    }
+
+    #[test]
+    fn interleaved_wells_summarise_like_grouped_ones() {
+        let agg = AggregateOptions {
+            features: vec!["Area".into()],
+            statistics: "count,mean,median,q25"
+                .split(',')
+                .map(|s| s.parse().unwrap())
+                .collect(),
+            ..AggregateOptions::default()
+        };
+        let grouped =
+            "Row\tColumn\tObject No\tArea\n1\t1\t1\t1\n1\t1\t2\t3\n1\t1\t3\tNaN\n1\t2\t1\t10\n";
+        let interleaved =
+            "Row\tColumn\tObject No\tArea\n1\t1\t1\t1\n1\t2\t1\t10\n1\t1\t2\t3\n1\t1\t3\tNaN\n";
+
+        let mut outputs = Vec::new();
+        for data in [grouped, interleaved] {
+            let dir = TestDir::new();
+            let file = Datafile::new("P1", Some("Nuclei"), data);
+            let path = dir.write("p/nuclei.txt", file);
+
+            let metadata = dir.metadata();
+            let columns = ["Row", "Column", "Area"].map(Arc::from);
+            let fits = summarise_grouped(&metadata[0], &columns, 2, &agg).unwrap();
+            assert_eq!(fits.is_some(), data == grouped);
+
+            let (out, report) = aggregate(&dir, agg.clone());
+            assert_eq!(report.rows[&path], 4);
+            outputs.push(out);
+        }
+        assert_eq!(outputs[0], outputs[1]);
+
+        let rows = cells(&outputs[0]);
+        let n = rows[0].len();
+        assert_eq!(
+            rows[0][n - 6..],
+            [
+                "Row",
+                "Column",
+                "Area - Count",
+                "Area - Mean",
+                "Area - Median",
+                "Area - Q25"
+            ]
+        );
+        assert_eq!(rows[1][n - 6..], ["1", "1", "2", "2", "2", "1.5"]);
+        assert_eq!(rows[2][n - 6..], ["1", "2", "1", "10", "10", "10"]);
+        assert_eq!(rows.len(), 3);
+    }
+
+    #[test]
+    fn by_field_and_default_features() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tField\tObject No\tArea\tRound\n\
+                    1\t1\t1\t1\t2\t0.5\n1\t1\t1\t2\t4\t0.7\n1\t1\t2\t1\t6\t0.9\n";
+        dir.write("p/nuclei.txt", Datafile::new("P1", Some("Nuclei"), data));
+
+        let agg = AggregateOptions {
+            statistics: vec![Statistic::Mean],
+            by_field: true,
+            ..AggregateOptions::default()
+        };
+        let (out, _) = aggregate(&dir, agg);
+        let rows = cells(&out);
+        let n = rows[0].len();
+        // ID columns are left out of the features
+        assert_eq!(
+            rows[0][n - 5..],
+            ["Row", "Column", "Field", "Area - Mean", "Round - Mean"]
+        );
+        assert_eq!(rows[1][n - 5..], ["1", "1", "1", "3", "0.6"]);
+        assert_eq!(rows[2][n - 5..], ["1", "1", "2", "6", "0.9"]);
+    }
+}
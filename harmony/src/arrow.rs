@@ -0,0 +1,284 @@
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+
+use arrow_array::{
+    builder::{Float64Builder, StringBuilder},
+    ArrayRef, RecordBatch,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+
+use crate::{
+    info::HarmonyMetadata,
+    sink::RecordSink,
+    utils::ValueKind,
+    write::{combine_into, CombineOptions, CombineReport, TEXT_FIELD_HDRS},
+};
+
+/// Rows in each record batch of the stream
+const BATCH_ROWS: usize = 8192;
+
+/// Write the combined data as an Arrow IPC stream, see [`ArrowSink`]
+pub fn write_arrow(
+    wtr: impl Write,
+    metadata: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    combine_into(ArrowSink::new(wtr), metadata, opts)
+}
+
+/// An Arrow IPC stream, written a record batch at a time.
+///
+/// Columns holding only numbers in the first batch are `Float64`, whether or not those
+/// are whole, so later rows can't hold a number that doesn't fit. Other columns, and
+/// ones with no values in the first batch, are `Utf8`, so nothing is written until the
+/// first batch is full or the rows run out. Text in a `Float64` column is an error. The
+/// plate name, evaluation signature and population are always `Utf8`, and empty cells
+/// are null.
+pub struct ArrowSink<W: Write> {
+    names: Vec<String>,
+    /// columns that are text whatever they hold
+    text: Vec<bool>,
+    /// the writer until the schema is known, then the stream
+    wtr: Option<W>,
+    stream: Option<(StreamWriter<W>, Arc<Schema>)>,
+    /// rows of the first batch, held until their types are known
+    pending: Vec<Vec<String>>,
+    columns: Vec<Column>,
+    rows: usize,
+}
+
+impl<W: Write> ArrowSink<W> {
+    pub fn new(wtr: W) -> Self {
+        Self {
+            names: Vec::new(),
+            text: Vec::new(),
+            wtr: Some(wtr),
+            stream: None,
+            pending: Vec::new(),
+            columns: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    /// Type the columns from the rows held so far, then write the schema and those rows
+    fn start(&mut self) -> io::Result<()> {
+        let mut kinds = self
+            .text
+            .iter()
+            .map(|&t| if t { ValueKind::Text } else { ValueKind::Empty })
+            .collect::<Vec<_>>();
+        for row in &self.pending {
+            for (k, v) in kinds.iter_mut().zip(row) {
+                *k = (*k).max(ValueKind::of(v));
+            }
+        }
+
+        self.columns = kinds.into_iter().map(Column::new).collect();
+        let schema = Arc::new(Schema::new(
+            self.names
+                .iter()
+                .zip(&self.columns)
+                .map(|(n, c)| Field::new(n, c.data_type(), true))
+                .collect::<Vec<_>>(),
+        ));
+        let wtr = self.wtr.take().expect("the stream is started once");
+        let stream = StreamWriter::try_new(wtr, &schema).map_err(arrow_err)?;
+        self.stream = Some((stream, schema));
+
+        for row in std::mem::take(&mut self.pending) {
+            let cells = row.iter().map(String::as_str).collect::<Vec<_>>();
+            self.append(&cells)?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, cells: &[&str]) -> io::Result<()> {
+        for ((c, v), name) in self.columns.iter_mut().zip(cells).zip(&self.names) {
+            if !c.append(v) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("column <{name}> held numbers in its first rows, but has <{v}>"),
+                ));
+            }
+        }
+        self.rows += 1;
+        if self.rows == BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        let (stream, schema) = self.stream.as_mut().expect("batches follow the schema");
+        let arrays = self.columns.iter_mut().map(Column::finish).collect();
+        let batch = RecordBatch::try_new(Arc::clone(schema), arrays).map_err(arrow_err)?;
+        self.rows = 0;
+        stream.write(&batch).map_err(arrow_err)
+    }
+}
+
+impl<W: Write> RecordSink for ArrowSink<W> {
+    fn begin_schema(&mut self, header: &[&str]) -> io::Result<()> {
+        self.names = header.iter().map(|n| n.to_string()).collect();
+        self.text = header.iter().map(|n| TEXT_FIELD_HDRS.contains(n)).collect();
+        Ok(())
+    }
+
+    fn write_row(&mut self, cells: &[&str]) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.pending
+                .push(cells.iter().map(|c| c.to_string()).collect());
+            if self.pending.len() == BATCH_ROWS {
+                self.start()?;
+            }
+            return Ok(());
+        }
+        self.append(cells)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.start()?;
+        }
+        if self.rows > 0 {
+            self.write_batch()?;
+        }
+        match &mut self.stream {
+            Some((stream, _)) => {
+                stream.finish().map_err(arrow_err)?;
+                stream.get_mut().flush()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Values of a column for the batch being built
+enum Column {
+    Real(Float64Builder),
+    Text(StringBuilder),
+}
+
+impl Column {
+    fn new(kind: ValueKind) -> Self {
+        match kind {
+            ValueKind::Integer | ValueKind::Real => {
+                Self::Real(Float64Builder::with_capacity(BATCH_ROWS))
+            }
+            ValueKind::Empty | ValueKind::Text => Self::Text(StringBuilder::new()),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Self::Real(_) => DataType::Float64,
+            Self::Text(_) => DataType::Utf8,
+        }
+    }
+
+    /// Append a cell, with empty cells as null, or return false for text in a number
+    /// column
+    fn append(&mut self, v: &str) -> bool {
+        if v.is_empty() {
+            match self {
+                Self::Real(b) => b.append_null(),
+                Self::Text(b) => b.append_null(),
+            }
+            return true;
+        }
+        match self {
+            Self::Real(b) => match v.parse() {
+                Ok(n) => b.append_value(n),
+                Err(_) => return false,
+            },
+            Self::Text(b) => b.append_value(v),
+        }
+        true
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Real(b) => Arc::new(b.finish()),
+            Self::Text(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+fn arrow_err(e: ArrowError) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Array};
+    use arrow_ipc::reader::StreamReader;
+
+    use super::*;
+
+    fn write(header: &[&str], rows: &[Vec<&str>]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut sink = ArrowSink::new(&mut out);
+        sink.begin_schema(header)?;
+        for row in rows {
+            sink.write_row(row)?;
+        }
+        sink.finish()?;
+        Ok(out)
+    }
+
+    #[test]
+    fn whole_numbers_then_a_real_one() {
+        let mut rows = (0..BATCH_ROWS + 100)
+            .map(|_| vec!["123", "7", "", "x"])
+            .collect::<Vec<_>>();
+        rows.push(vec!["123", "12.5", "3", "007"]);
+        let out = write(&["Plate Name", "Count", "Late", "Label"], &rows).unwrap();
+
+        let rdr = StreamReader::try_new(Cursor::new(out), None).unwrap();
+        let types = rdr
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.data_type().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                DataType::Utf8,
+                DataType::Float64,
+                DataType::Utf8,
+                DataType::Utf8
+            ]
+        );
+        let batches = rdr.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).sum::<usize>(),
+            rows.len()
+        );
+
+        let last = batches.last().unwrap();
+        let counts = last.column(1).as_primitive::<Float64Type>();
+        assert_eq!(counts.value(last.num_rows() - 1), 12.5);
+        assert_eq!(counts.value(0), 7.0);
+        let late = last.column(2).as_string::<i32>();
+        assert_eq!(late.value(last.num_rows() - 1), "3");
+        assert!(late.is_null(0));
+    }
+
+    #[test]
+    fn text_in_a_number_column() {
+        let mut rows = vec![vec!["1.5"]; BATCH_ROWS];
+        rows.push(vec!["n/a"]);
+        let err = write(&["Area"], &rows).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "column <Area> held numbers in its first rows, but has <n/a>"
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use std::{collections::HashMap, io};
+
+use crate::{
+    stats::{self, parse_value, MAD_SCALE},
+    table::{missing_column, Table, PLATE_GROUP_HDRS, TIMEPOINT_HDR},
+};
+
+/// Correct row, column and edge effects of each plate with a B-score
+#[derive(Debug, Clone)]
+pub struct BScoreOptions {
+    /// columns to correct
+    pub features: Vec<String>,
+    /// correct each timepoint of a plate on its own
+    pub per_timepoint: bool,
+    /// most sweeps of the median polish
+    pub max_iterations: usize,
+    /// stop polishing once a sweep moves the values by less than this in total
+    pub tolerance: f64,
+}
+
+impl Default for BScoreOptions {
+    fn default() -> Self {
+        Self {
+            features: Vec::new(),
+            per_timepoint: false,
+            max_iterations: 10,
+            tolerance: 1e-6,
+        }
+    }
+}
+
+/// Add a `{feature} - B-score` column after each feature's raw column.
+///
+/// A two-way median polish over the Row/Column position of each well takes out
+/// the row and column effects of a plate, and the residuals are scaled by their
+/// MAD. Plates are split the same way as for [`normalise`](crate::normalise);
+/// rows without a well position or a value are left empty.
+pub fn b_score(table: &mut Table, opts: &BScoreOptions) -> io::Result<()> {
+    let locator = table.locator()?;
+    let mut group_hdrs = PLATE_GROUP_HDRS.to_vec();
+    if opts.per_timepoint {
+        table
+            .column(TIMEPOINT_HDR)
+            .ok_or_else(|| missing_column(TIMEPOINT_HDR))?;
+        group_hdrs.push(TIMEPOINT_HDR);
+    }
+    let wells = (0..table.rows.len())
+        .map(|row| locator.well(table, row))
+        .collect::<Vec<_>>();
+    let groups = table
+        .group_by(&group_hdrs)
+        .into_values()
+        .collect::<Vec<_>>();
+
+    for feature in &opts.features {
+        let col = table
+            .column(feature)
+            .ok_or_else(|| missing_column(feature))?;
+        let mut scores = vec![String::new(); table.rows.len()];
+
+        for rows in &groups {
+            let cells = rows
+                .iter()
+                .filter_map(|&r| {
+                    let well = wells[r]?;
+                    let v = parse_value(table.cell(r, col))?;
+                    Some((r, well.row, well.column, v))
+                })
+                .collect::<Vec<_>>();
+            if cells.is_empty() {
+                continue;
+            }
+
+            let positions = cells
+                .iter()
+                .map(|&(_, row, column, _)| (row, column))
+                .collect::<Vec<_>>();
+            let mut residuals = cells.iter().map(|c| c.3).collect::<Vec<_>>();
+            median_polish(&positions, &mut residuals, opts);
+
+            let scale = MAD_SCALE * stats::mad(&residuals);
+            for (&(r, ..), res) in cells.iter().zip(residuals) {
+                let b = res / scale;
+                if b.is_finite() {
+                    scores[r] = b.to_string();
+                }
+            }
+        }
+
+        table.insert_column(col + 1, &format!("{feature} - B-score"), scores);
+    }
+
+    Ok(())
+}
+
+/// Sweep row then column medians out of the values until they stop changing,
+/// leaving the residuals. Wells may hold any number of values, including none.
+fn median_polish(positions: &[(u32, u32)], values: &mut [f64], opts: &BScoreOptions) {
+    let mut by_row: HashMap<u32, Vec<usize>> = HashMap::new();
+    let mut by_column: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, &(row, column)) in positions.iter().enumerate() {
+        by_row.entry(row).or_default().push(i);
+        by_column.entry(column).or_default().push(i);
+    }
+
+    for _ in 0..opts.max_iterations {
+        let mut change = 0.0;
+        for lines in [&by_row, &by_column] {
+            for idx in lines.values() {
+                let line = idx.iter().map(|&i| values[i]).collect::<Vec<_>>();
+                let m = stats::median(&line);
+                for &i in idx {
+                    values[i] -= m;
+                }
+                change += m.abs();
+            }
+        }
+        if change < opts.tolerance {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(rows: &[&[f64]]) -> (Vec<(u32, u32)>, Vec<f64>) {
+        let mut positions = Vec::new();
+        let mut values = Vec::new();
+        for (r, row) in (1..).zip(rows) {
+            for (c, &v) in (1..).zip(*row) {
+                positions.push((r, c));
+                values.push(v);
+            }
+        }
+        (positions, values)
+    }
+
+    #[test]
+    fn polish_leaves_residuals() {
+        // row medians 2, 5, 8 leave columns -1, 0, 1 with medians -1, 0, 1
+        let (positions, mut values) =
+            grid(&[&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0], &[7.0, 8.0, 10.0]]);
+        median_polish(&positions, &mut values, &BScoreOptions::default());
+        assert_eq!(values, [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn polish_takes_out_additive_effects() {
+        // 10 + row effect + column effect, with one outlier the medians ignore
+        let mut rows = [[0.0; 4]; 3];
+        for (r, row) in rows.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = 10.0 + 3.0 * r as f64 - 2.0 * c as f64;
+            }
+        }
+        rows[1][2] += 50.0;
+        let rows = rows.iter().map(|r| &r[..]).collect::<Vec<_>>();
+        let (positions, mut values) = grid(&rows);
+        median_polish(&positions, &mut values, &BScoreOptions::default());
+
+        let mut expected = [0.0; 12];
+        expected[6] = 50.0;
+        assert_eq!(values, expected);
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use regex::Regex;
+
+use crate::{
+    stats::{self, parse_value},
+    table::{missing_column, Table},
+    well::Well,
+};
+
+/// How plates are grouped into replicate sets
+#[derive(Debug, Clone)]
+pub enum ReplicateGrouping {
+    /// plates are replicates when their names are equal after removing the first
+    /// match of the pattern, e.g. `_rep\d+`
+    Pattern(Regex),
+    /// plates are replicates when they have the same value in a column of the
+    /// combined data, such as a plate map or plate name pattern column
+    Column(String),
+}
+
+impl ReplicateGrouping {
+    pub fn pattern(re: &str) -> Result<Self, String> {
+        Regex::new(re)
+            .map(Self::Pattern)
+            .map_err(|e| format!("invalid replicate pattern: {e}"))
+    }
+}
+
+/// Compare replicate plates well by well
+#[derive(Debug, Clone)]
+pub struct ConcordanceOptions {
+    pub features: Vec<String>,
+    pub grouping: ReplicateGrouping,
+    /// flag wells whose replicate difference is further than this many standard
+    /// deviations from the mean difference of the plate pair
+    pub max_disagreement: f64,
+}
+
+/// Agreement of one feature between two replicate plates
+#[derive(Debug, Clone)]
+pub struct ReplicatePair {
+    pub set: String,
+    pub feature: String,
+    pub plate_a: String,
+    pub plate_b: String,
+    /// wells with a value on both plates
+    pub wells: usize,
+    pub pearson: f64,
+    pub spearman: f64,
+    /// Bland–Altman mean of the differences `a - b`
+    pub bias: f64,
+    /// standard deviation of the differences
+    pub sd_difference: f64,
+}
+
+impl ReplicatePair {
+    /// Bland–Altman 95% limits of agreement
+    pub fn limits_of_agreement(&self) -> (f64, f64) {
+        let half = 1.96 * self.sd_difference;
+        (self.bias - half, self.bias + half)
+    }
+}
+
+/// A well whose replicate values disagree more than allowed
+#[derive(Debug, Clone)]
+pub struct FlaggedWell {
+    pub set: String,
+    pub feature: String,
+    pub plate_a: String,
+    pub plate_b: String,
+    pub well: Well,
+    pub value_a: f64,
+    pub value_b: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConcordanceReport {
+    pub pairs: Vec<ReplicatePair>,
+    pub flagged: Vec<FlaggedWell>,
+    /// plates without any replicate
+    pub unpaired: Vec<String>,
+}
+
+const PAIR_HDRS: &[&str] = &[
+    "Replicate Set",
+    "Feature",
+    "Plate A",
+    "Plate B",
+    "Wells",
+    "Pearson",
+    "Spearman",
+    "Bias",
+    "SD Difference",
+    "Lower Limit of Agreement",
+    "Upper Limit of Agreement",
+];
+
+const FLAGGED_HDRS: &[&str] = &[
+    "Replicate Set",
+    "Feature",
+    "Plate A",
+    "Plate B",
+    "Well",
+    "Value A",
+    "Value B",
+    "Difference",
+];
+
+impl ConcordanceReport {
+    /// Write the correlations and Bland–Altman summary of every replicate pair
+    pub fn write_pairs_tsv(&self, mut wtr: impl Write) -> io::Result<()> {
+        writeln!(wtr, "{}", PAIR_HDRS.join("\t"))?;
+        for p in &self.pairs {
+            write!(
+                wtr,
+                "{}\t{}\t{}\t{}\t{}",
+                p.set, p.feature, p.plate_a, p.plate_b, p.wells
+            )?;
+            let (lower, upper) = p.limits_of_agreement();
+            for v in [p.pearson, p.spearman, p.bias, p.sd_difference, lower, upper] {
+                write_value(&mut wtr, v)?;
+            }
+            writeln!(wtr)?;
+        }
+        Ok(())
+    }
+
+    /// Write the wells flagged for replicate disagreement
+    pub fn write_flagged_tsv(&self, mut wtr: impl Write) -> io::Result<()> {
+        writeln!(wtr, "{}", FLAGGED_HDRS.join("\t"))?;
+        for f in &self.flagged {
+            write!(
+                wtr,
+                "{}\t{}\t{}\t{}\t{}",
+                f.set, f.feature, f.plate_a, f.plate_b, f.well
+            )?;
+            for v in [f.value_a, f.value_b, f.value_a - f.value_b] {
+                write_value(&mut wtr, v)?;
+            }
+            writeln!(wtr)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_value(wtr: &mut impl Write, v: f64) -> io::Result<()> {
+    if v.is_finite() {
+        write!(wtr, "\t{v}")
+    } else {
+        write!(wtr, "\t")
+    }
+}
+
+/// Correlate every pair of replicate plates, well by well, for each feature.
+///
+/// A plate's value for a well is the mean of its rows for that well, so fields
+/// and timepoints are pooled. Wells are flagged when their difference between a
+/// pair of plates is an outlier among that pair's differences.
+pub fn replicate_concordance(
+    table: &Table,
+    opts: &ConcordanceOptions,
+) -> io::Result<ConcordanceReport> {
+    let locator = table.locator()?;
+    enum SetKey<'a> {
+        Pattern(&'a Regex),
+        Column(usize),
+    }
+    let set_key = match &opts.grouping {
+        ReplicateGrouping::Pattern(re) => SetKey::Pattern(re),
+        ReplicateGrouping::Column(c) => {
+            SetKey::Column(table.column(c).ok_or_else(|| missing_column(c))?)
+        }
+    };
+
+    // rows of each well of each plate, by replicate set
+    type Plates<'a> = BTreeMap<&'a str, BTreeMap<Well, Vec<usize>>>;
+    let mut sets: BTreeMap<String, Plates> = BTreeMap::new();
+    for row in 0..table.rows.len() {
+        let plate = locator.plate(table, row);
+        let well = match locator.well(table, row) {
+            Some(w) => w,
+            None => continue,
+        };
+        let set = match set_key {
+            SetKey::Pattern(re) => re.replace(plate, "").into_owned(),
+            SetKey::Column(c) => table.cell(row, c).to_string(),
+        };
+        sets.entry(set)
+            .or_default()
+            .entry(plate)
+            .or_default()
+            .entry(well)
+            .or_default()
+            .push(row);
+    }
+
+    let mut report = ConcordanceReport::default();
+    for plates in sets.values() {
+        if plates.len() < 2 {
+            report.unpaired.extend(plates.keys().map(|p| p.to_string()));
+        }
+    }
+
+    for feature in &opts.features {
+        let col = table
+            .column(feature)
+            .ok_or_else(|| missing_column(feature))?;
+        for (set, plates) in &sets {
+            // mean value of each well on each plate
+            let means = plates
+                .iter()
+                .map(|(plate, wells)| {
+                    let values = wells
+                        .iter()
+                        .filter_map(|(well, rows)| {
+                            let vals = rows
+                                .iter()
+                                .filter_map(|&r| parse_value(table.cell(r, col)))
+                                .collect::<Vec<_>>();
+                            (!vals.is_empty()).then(|| (*well, stats::mean(&vals)))
+                        })
+                        .collect::<BTreeMap<_, _>>();
+                    (*plate, values)
+                })
+                .filter(|(_, values)| !values.is_empty())
+                .collect::<Vec<_>>();
+
+            for (i, (plate_a, a)) in means.iter().enumerate() {
+                for (plate_b, b) in &means[i + 1..] {
+                    let paired = a
+                        .iter()
+                        .filter_map(|(w, va)| b.get(w).map(|vb| (*w, *va, *vb)))
+                        .collect::<Vec<_>>();
+                    let xs = paired.iter().map(|p| p.1).collect::<Vec<_>>();
+                    let ys = paired.iter().map(|p| p.2).collect::<Vec<_>>();
+                    let diffs = paired.iter().map(|p| p.1 - p.2).collect::<Vec<_>>();
+                    let bias = stats::mean(&diffs);
+                    let sd = stats::std_dev(&diffs);
+
+                    for &(well, va, vb) in &paired {
+                        if (va - vb - bias).abs() > opts.max_disagreement * sd {
+                            report.flagged.push(FlaggedWell {
+                                set: set.clone(),
+                                feature: feature.clone(),
+                                plate_a: plate_a.to_string(),
+                                plate_b: plate_b.to_string(),
+                                well,
+                                value_a: va,
+                                value_b: vb,
+                            });
+                        }
+                    }
+                    report.pairs.push(ReplicatePair {
+                        set: set.clone(),
+                        feature: feature.clone(),
+                        plate_a: plate_a.to_string(),
+                        plate_b: plate_b.to_string(),
+                        wells: paired.len(),
+                        pearson: stats::pearson(&xs, &ys),
+                        spearman: stats::spearman(&xs, &ys),
+                        bias,
+                        sd_difference: sd,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::table;
+
+    fn replicates() -> Table {
+        let mut text = String::from("Plate Name\tSet\tRow\tColumn\tCount\n");
+        for (c, (a, b)) in (1..).zip([(1, 2), (2, 3), (3, 4), (4, 5), (5, 6), (6, 20)]) {
+            text.push_str(&format!(
+                "HX_rep1\tX\t1\t{c}\t{a}\nHX_rep2\tX\t1\t{c}\t{b}\n"
+            ));
+        }
+        // fields of a well are pooled
+        text.push_str("HX_rep1\tX\t1\t1\t\nHX_rep1\tX\t1\t1\t1\nSolo\tY\t1\t1\t4\n");
+        table(&text)
+    }
+
+    fn options(grouping: ReplicateGrouping) -> ConcordanceOptions {
+        ConcordanceOptions {
+            features: vec!["Count".into()],
+            grouping,
+            max_disagreement: 2.0,
+        }
+    }
+
+    #[test]
+    fn pairs_of_replicate_plates() {
+        let table = replicates();
+        let opts = options(ReplicateGrouping::pattern(r"_rep\d+").unwrap());
+        let report = replicate_concordance(&table, &opts).unwrap();
+
+        assert_eq!(report.unpaired, ["Solo"]);
+        assert_eq!(report.pairs.len(), 1);
+        let pair = &report.pairs[0];
+        assert_eq!(
+            (
+                pair.set.as_str(),
+                pair.plate_a.as_str(),
+                pair.plate_b.as_str()
+            ),
+            ("HX", "HX_rep1", "HX_rep2")
+        );
+        assert_eq!(pair.wells, 6);
+        assert_eq!(pair.spearman, 1.0);
+        assert!(pair.pearson < 1.0);
+        assert!((pair.bias - -19.0 / 6.0).abs() < 1e-12);
+
+        // A06 is 14 apart where the others are 1 apart
+        let flagged = report
+            .flagged
+            .iter()
+            .map(|f| (f.well.to_string(), f.value_a, f.value_b))
+            .collect::<Vec<_>>();
+        assert_eq!(flagged, [("A06".to_string(), 6.0, 20.0)]);
+
+        let mut out = Vec::new();
+        report.write_flagged_tsv(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out.lines().nth(1),
+            Some("HX\tCount\tHX_rep1\tHX_rep2\tA06\t6\t20\t-14")
+        );
+    }
+
+    #[test]
+    fn replicate_sets_from_a_column() {
+        let table = replicates();
+        let report =
+            replicate_concordance(&table, &options(ReplicateGrouping::Column("Set".into())))
+                .unwrap();
+        assert_eq!(report.pairs[0].set, "X");
+        assert_eq!(report.unpaired, ["Solo"]);
+
+        let missing = options(ReplicateGrouping::Column("Replicate".into()));
+        assert!(replicate_concordance(&table, &missing).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use std::{fmt, io, str::FromStr};
+
+use serde::{
+    de::{
+        self, value::BorrowedStrDeserializer, DeserializeOwned, DeserializeSeed, MapAccess, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+
+use crate::{
+    rows::{Row, Rows, Value},
+    write::population_label,
+};
+
+impl<'r> Row<'r> {
+    /// Deserialize the row into `T`, matching field names (or their `#[serde(rename)]`
+    /// and `#[serde(alias)]` names) to the `Plate Name`, `Measurement`, `Evaluation`,
+    /// `Evaluation Signature` and `Population` metadata columns, the columns added by
+    /// [`Rows::with_options`] such as the well ID, and the data columns the datafile
+    /// has.
+    ///
+    /// Cells are parsed as whatever type the field has. Empty cells are `None` for
+    /// `Option` fields, and `Option` fields whose column the datafile lacks are `None`
+    /// as well. Errors name the datafile and line.
+    pub fn deserialize<T: Deserialize<'r>>(&self) -> io::Result<T> {
+        T::deserialize(RowDeserializer { row: *self }).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} line {}: {e}", self.metadata.path.display(), self.line),
+            )
+        })
+    }
+}
+
+impl Rows<'_> {
+    /// Read the next row and deserialize it, see [`Row::deserialize`]
+    pub fn deserialize<T: DeserializeOwned>(&mut self) -> Option<io::Result<T>> {
+        self.next_row().map(|row| row.and_then(|r| r.deserialize()))
+    }
+}
+
+#[derive(Debug)]
+struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+/// A row as a map of column names to cells
+struct RowDeserializer<'r> {
+    row: Row<'r>,
+}
+
+impl<'de> Deserializer<'de> for RowDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let m = self.row.metadata;
+        let metadata = vec![
+            ("Plate Name", Cell::Text(&m.plate_name)),
+            ("Measurement", Cell::Number(m.measurement)),
+            ("Evaluation", Cell::Number(m.evaluation)),
+            ("Evaluation Signature", Cell::Text(&m.eval_sig)),
+            ("Population", Cell::Text(population_label(m))),
+        ];
+        let added = self.row.meta_columns().map(|(h, v)| (h, Cell::Text(v)));
+        let data = self
+            .row
+            .header
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, h)| Some((h.as_ref(), Cell::Text(self.row.get(i)?))));
+        visitor.visit_map(Fields {
+            fields: metadata.into_iter().chain(added).chain(data),
+            value: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct Fields<'de, I> {
+    fields: I,
+    value: Option<(&'de str, Cell<'de>)>,
+}
+
+impl<'de, I: Iterator<Item = (&'de str, Cell<'de>)>> MapAccess<'de> for Fields<'de, I> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.fields.next() {
+            Some((name, cell)) => {
+                self.value = Some((name, cell));
+                seed.deserialize(BorrowedStrDeserializer::new(name))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (name, cell) = self.value.take().expect("value follows its key");
+        seed.deserialize(cell)
+            .map_err(|e| Error(format!("column <{name}>: {e}")))
+    }
+}
+
+/// One cell of a row, or a metadata field
+#[derive(Clone, Copy)]
+enum Cell<'de> {
+    Text(&'de str),
+    Number(u32),
+}
+
+impl Cell<'_> {
+    fn parse<T: FromStr>(self) -> Result<T, Error>
+    where
+        T::Err: fmt::Display,
+    {
+        match self {
+            Cell::Text("") => Err(Error("empty cell".into())),
+            Cell::Text(s) => s
+                .trim()
+                .parse()
+                .map_err(|e| Error(format!("can't read <{s}>: {e}"))),
+            Cell::Number(n) => n
+                .to_string()
+                .parse()
+                .map_err(|e| Error(format!("can't read <{n}>: {e}"))),
+        }
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Cell<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Cell::Number(n) => visitor.visit_u32(n),
+            Cell::Text(s) => match Value::parse(s) {
+                Value::Null => visitor.visit_none(),
+                Value::Integer(n) => visitor.visit_i64(n),
+                Value::Real(n) => visitor.visit_f64(n),
+                Value::Text(s) => visitor.visit_borrowed_str(s),
+            },
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Cell::Text(s) => visitor.visit_borrowed_str(s),
+            Cell::Number(n) => visitor.visit_string(n.to_string()),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Cell::Text("") => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Cell::Text(s) => {
+                BorrowedStrDeserializer::new(s).deserialize_enum(name, variants, visitor)
+            }
+            Cell::Number(n) => Err(de::Error::invalid_type(
+                de::Unexpected::Unsigned(n.into()),
+                &visitor,
+            )),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use serde::Deserialize;
+
+    use crate::{collect_harmony_datafiles, CombineOptions, Rows, WellOptions};
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct NucleiRow {
+        #[serde(rename = "Plate Name")]
+        plate: String,
+        #[serde(rename = "Well")]
+        well: String,
+        #[serde(rename = "Nuclei - Area [µm²]")]
+        area: f64,
+    }
+
+    /// A directory holding one nuclei datafile with the given data lines
+    fn datafile(name: &str, data: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("harmony-de-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut text = "Database Name\tHarmonyDB\nDatabase Location\tdb\n\
+            Evaluation Signature\tsig\nPlate Name\tHX1\nMeasurement\tMeasurement 1\n\
+            Evaluation\tEvaluation1\nPopulation\tNuclei\n\n[Data]\n\
+            Row\tColumn\tNuclei - Area [µm²]\n"
+            .to_string();
+        for line in data {
+            text.push_str(line);
+            text.push('\n');
+        }
+        fs::write(dir.join("nuclei.txt"), text).unwrap();
+        dir
+    }
+
+    fn read<T: serde::de::DeserializeOwned>(dir: &PathBuf) -> Vec<std::io::Result<T>> {
+        let metadata = collect_harmony_datafiles(dir);
+        let opts = CombineOptions {
+            well: Some(WellOptions::default()),
+            ..CombineOptions::default()
+        };
+        let mut rows = Rows::with_options(&metadata, &opts).unwrap();
+        let mut out = Vec::new();
+        while let Some(row) = rows.deserialize() {
+            out.push(row);
+        }
+        fs::remove_dir_all(dir).unwrap();
+        out
+    }
+
+    #[test]
+    fn combined_columns() {
+        let dir = datafile("combined", &["1\t1\t116.5", "", "2\t12\t98"]);
+        let rows = read::<NucleiRow>(&dir)
+            .into_iter()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        let row = |well: &str, area| NucleiRow {
+            plate: "HX1".into(),
+            well: well.into(),
+            area,
+        };
+        assert_eq!(rows, [row("A01", 116.5), row("B12", 98.0)]);
+    }
+
+    #[test]
+    fn errors_name_file_and_line() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct WithVolume {
+            #[serde(rename = "Nuclei - Volume")]
+            volume: f64,
+        }
+
+        let dir = datafile("missing", &["1\t1\t116.5"]);
+        let path = dir.join("nuclei.txt");
+        let err = read::<WithVolume>(&dir).remove(0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} line 11: missing field `Nuclei - Volume`",
+                path.display()
+            )
+        );
+
+        let dir = datafile("mistyped", &["1\t1\t116.5", "1\t2\tn/a"]);
+        let path = dir.join("nuclei.txt");
+        let mut rows = read::<NucleiRow>(&dir);
+        assert!(rows[0].is_ok());
+        let err = rows.remove(1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} line 12: column <Nuclei - Area [µm²]>: can't read <n/a>: invalid float literal",
+                path.display()
+            )
+        );
+    }
+}
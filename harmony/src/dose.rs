@@ -0,0 +1,408 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use crate::{
+    stats::{self, parse_value},
+    table::{missing_column, Table},
+};
+
+/// Fit four-parameter logistic curves to each compound's concentration series
+#[derive(Debug, Clone)]
+pub struct DoseResponseOptions {
+    /// columns to fit, such as raw or normalised features
+    pub features: Vec<String>,
+    /// plate map column naming the compound in a well
+    pub compound_column: String,
+    /// plate map column with the compound's concentration
+    pub concentration_column: String,
+}
+
+impl Default for DoseResponseOptions {
+    fn default() -> Self {
+        Self {
+            features: Vec::new(),
+            compound_column: "Compound".into(),
+            concentration_column: "Concentration".into(),
+        }
+    }
+}
+
+/// A fitted parameter and its 95% confidence interval
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Estimate {
+    const MISSING: Self = Self {
+        value: f64::NAN,
+        lower: f64::NAN,
+        upper: f64::NAN,
+    };
+}
+
+/// 4PL curve of one compound and feature:
+/// `bottom + (top - bottom) / (1 + (ec50 / x) ^ hill)`
+#[derive(Debug, Clone)]
+pub struct DoseResponseFit {
+    pub compound: String,
+    pub feature: String,
+    /// number of values, pooled over replicate wells and plates
+    pub n_points: usize,
+    pub n_concentrations: usize,
+    pub bottom: Estimate,
+    pub top: Estimate,
+    /// concentration of the half-maximal response; the IC50 when the curve falls
+    pub ec50: Estimate,
+    /// negative for curves that fall with concentration
+    pub hill: Estimate,
+    pub r_squared: f64,
+    /// false when too few concentrations were tested or the fit did not settle
+    pub converged: bool,
+}
+
+const FIT_HDRS: &[&str] = &[
+    "Compound",
+    "Feature",
+    "Points",
+    "Concentrations",
+    "EC50",
+    "EC50 Lower",
+    "EC50 Upper",
+    "Hill Slope",
+    "Hill Slope Lower",
+    "Hill Slope Upper",
+    "Top",
+    "Top Lower",
+    "Top Upper",
+    "Bottom",
+    "Bottom Lower",
+    "Bottom Upper",
+    "R²",
+    "Converged",
+];
+
+/// Write the fits as a tab separated results table
+pub fn write_dose_response(mut wtr: impl Write, fits: &[DoseResponseFit]) -> io::Result<()> {
+    writeln!(wtr, "{}", FIT_HDRS.join("\t"))?;
+    for fit in fits {
+        write!(
+            wtr,
+            "{}\t{}\t{}\t{}",
+            fit.compound, fit.feature, fit.n_points, fit.n_concentrations
+        )?;
+        let estimates = [fit.ec50, fit.hill, fit.top, fit.bottom];
+        let values = estimates
+            .iter()
+            .flat_map(|e| [e.value, e.lower, e.upper])
+            .chain([fit.r_squared]);
+        for v in values {
+            if v.is_finite() {
+                write!(wtr, "\t{v}")?;
+            } else {
+                write!(wtr, "\t")?;
+            }
+        }
+        writeln!(wtr, "\t{}", if fit.converged { "yes" } else { "no" })?;
+    }
+    Ok(())
+}
+
+/// Fit a 4PL curve for every compound and feature.
+///
+/// Rows with a compound and a positive concentration (read from the plate map
+/// columns joined onto the table) are pooled across plates, so replicate plates
+/// fit a single curve. Compounds with fewer than four concentrations are still
+/// listed, without estimates.
+pub fn fit_dose_response(
+    table: &Table,
+    opts: &DoseResponseOptions,
+) -> io::Result<Vec<DoseResponseFit>> {
+    let compound_col = table
+        .column(&opts.compound_column)
+        .ok_or_else(|| missing_column(&opts.compound_column))?;
+    let conc_col = table
+        .column(&opts.concentration_column)
+        .ok_or_else(|| missing_column(&opts.concentration_column))?;
+    let feature_cols = opts
+        .features
+        .iter()
+        .map(|f| table.column(f).ok_or_else(|| missing_column(f)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // rows of each compound, with the log10 concentration
+    let mut compounds: BTreeMap<&str, Vec<(usize, f64)>> = BTreeMap::new();
+    for row in 0..table.rows.len() {
+        let compound = table.cell(row, compound_col).trim();
+        let conc = parse_value(table.cell(row, conc_col)).filter(|c| *c > 0.0);
+        if let (false, Some(c)) = (compound.is_empty(), conc) {
+            compounds
+                .entry(compound)
+                .or_default()
+                .push((row, c.log10()));
+        }
+    }
+
+    let mut fits = Vec::new();
+    for (compound, rows) in &compounds {
+        for (feature, &col) in opts.features.iter().zip(&feature_cols) {
+            if !table.has_feature(col, rows.iter().map(|&(r, _)| r)) {
+                continue;
+            }
+            let points = rows
+                .iter()
+                .filter_map(|&(r, x)| parse_value(table.cell(r, col)).map(|y| (x, y)))
+                .collect::<Vec<_>>();
+            fits.push(fit_curve(compound, feature, &points));
+        }
+    }
+
+    Ok(fits)
+}
+
+fn fit_curve(compound: &str, feature: &str, points: &[(f64, f64)]) -> DoseResponseFit {
+    let mut concentrations = points.iter().map(|p| p.0).collect::<Vec<_>>();
+    stats::sort(&mut concentrations);
+    concentrations.dedup();
+
+    let mut fit = DoseResponseFit {
+        compound: compound.to_string(),
+        feature: feature.to_string(),
+        n_points: points.len(),
+        n_concentrations: concentrations.len(),
+        bottom: Estimate::MISSING,
+        top: Estimate::MISSING,
+        ec50: Estimate::MISSING,
+        hill: Estimate::MISSING,
+        r_squared: f64::NAN,
+        converged: false,
+    };
+    if concentrations.len() < 4 {
+        return fit;
+    }
+
+    let (params, converged) = levenberg_marquardt(points, initial_guess(points));
+    let sse = sum_sq_residuals(points, &params);
+    let mean_y = stats::mean(&points.iter().map(|p| p.1).collect::<Vec<_>>());
+    let sst = points.iter().map(|p| (p.1 - mean_y).powi(2)).sum::<f64>();
+
+    // standard errors from the covariance at the solution
+    let dof = points.len().saturating_sub(4);
+    let t = stats::t_critical_95(dof);
+    let s2 = sse / dof as f64;
+    let (jtj, _) = normal_equations(points, &params);
+    let cov = invert(jtj);
+    let estimate = |i: usize| {
+        let half = cov.map_or(f64::NAN, |c| t * (s2 * c[i][i]).sqrt());
+        Estimate {
+            value: params[i],
+            lower: params[i] - half,
+            upper: params[i] + half,
+        }
+    };
+
+    fit.bottom = estimate(0);
+    fit.top = estimate(1);
+    // the fit is on log10 concentration, so the EC50 interval isn't symmetric
+    let log_ec50 = estimate(2);
+    fit.ec50 = Estimate {
+        value: 10f64.powf(log_ec50.value),
+        lower: 10f64.powf(log_ec50.lower),
+        upper: 10f64.powf(log_ec50.upper),
+    };
+    fit.hill = estimate(3);
+    fit.r_squared = 1.0 - sse / sst;
+    fit.converged = converged;
+    fit
+}
+
+/// Parameters are `[bottom, top, log10 ec50, hill]`, fit against log10 concentrations
+type Params = [f64; 4];
+
+fn model(p: &Params, x: f64) -> f64 {
+    p[0] + (p[1] - p[0]) / (1.0 + 10f64.powf((p[2] - x) * p[3]))
+}
+
+/// Model value and partial derivatives by each parameter
+fn gradient(p: &Params, x: f64) -> (f64, Params) {
+    let u = 10f64.powf((p[2] - x) * p[3]);
+    let d = 1.0 + u;
+    let span = p[1] - p[0];
+    let dk = -span * u * std::f64::consts::LN_10 / (d * d);
+    (
+        p[0] + span / d,
+        [1.0 - 1.0 / d, 1.0 / d, dk * p[3], dk * (p[2] - x)],
+    )
+}
+
+fn initial_guess(points: &[(f64, f64)]) -> Params {
+    let xs = points.iter().map(|p| p.0).collect::<Vec<_>>();
+    let ys = points.iter().map(|p| p.1).collect::<Vec<_>>();
+    let lo = ys.iter().copied().fold(f64::INFINITY, f64::min);
+    let hi = ys.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+    // rising or falling, from the covariance of response and log concentration
+    let (mx, my) = (stats::mean(&xs), stats::mean(&ys));
+    let cov = points.iter().map(|(x, y)| (x - mx) * (y - my)).sum::<f64>();
+    let hill = if cov < 0.0 { -1.0 } else { 1.0 };
+
+    [lo, hi, stats::median(&xs), hill]
+}
+
+fn sum_sq_residuals(points: &[(f64, f64)], p: &Params) -> f64 {
+    points.iter().map(|&(x, y)| (y - model(p, x)).powi(2)).sum()
+}
+
+/// `JᵀJ` and `Jᵀr` of the residuals at the parameters
+fn normal_equations(points: &[(f64, f64)], p: &Params) -> ([Params; 4], Params) {
+    let mut jtj = [[0.0; 4]; 4];
+    let mut jtr = [0.0; 4];
+    for &(x, y) in points {
+        let (f, g) = gradient(p, x);
+        for i in 0..4 {
+            jtr[i] += g[i] * (y - f);
+            for j in 0..4 {
+                jtj[i][j] += g[i] * g[j];
+            }
+        }
+    }
+    (jtj, jtr)
+}
+
+/// Least squares fit, returning the parameters and whether they settled
+fn levenberg_marquardt(points: &[(f64, f64)], mut p: Params) -> (Params, bool) {
+    const MAX_ITERATIONS: usize = 200;
+
+    let mut lambda = 1e-3;
+    let mut sse = sum_sq_residuals(points, &p);
+    for _ in 0..MAX_ITERATIONS {
+        let (jtj, jtr) = normal_equations(points, &p);
+        loop {
+            let mut a = jtj;
+            for (i, row) in a.iter_mut().enumerate() {
+                row[i] += lambda * jtj[i][i].max(1e-12);
+            }
+            let step = solve(a, jtr);
+            let next = step.map(|s| [p[0] + s[0], p[1] + s[1], p[2] + s[2], p[3] + s[3]]);
+            let next_sse = next.map_or(f64::NAN, |n| sum_sq_residuals(points, &n));
+
+            if next_sse <= sse {
+                let settled = sse - next_sse <= 1e-12 * sse.max(f64::MIN_POSITIVE);
+                p = next.expect("finite sse has parameters");
+                sse = next_sse;
+                lambda = (lambda / 10.0).max(1e-12);
+                if settled {
+                    return (p, true);
+                }
+                break;
+            }
+            lambda *= 10.0;
+            if lambda > 1e12 {
+                // no step improves the fit, so this is the minimum
+                return (p, sse.is_finite());
+            }
+        }
+    }
+    (p, false)
+}
+
+/// Solve `a x = b` by Gaussian elimination with partial pivoting
+fn solve(mut a: [Params; 4], mut b: Params) -> Option<Params> {
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-300 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in col + 1..4 {
+            let factor = a[row][col] / pivot_row[col];
+            for (v, p) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *v -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; 4];
+    for row in (0..4).rev() {
+        let rest = (row + 1..4).map(|k| a[row][k] * x[k]).sum::<f64>();
+        x[row] = (b[row] - rest) / a[row][row];
+    }
+    x.iter().all(|v| v.is_finite()).then_some(x)
+}
+
+fn invert(a: [Params; 4]) -> Option<[Params; 4]> {
+    let mut inv = [[0.0; 4]; 4];
+    for i in 0..4 {
+        let mut e = [0.0; 4];
+        e[i] = 1.0;
+        let col = solve(a, e)?;
+        for (row, v) in inv.iter_mut().zip(col) {
+            row[i] = v;
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Responses of a known curve at half-log steps from 1 nM to 1 mM, with `noise`
+    /// added and subtracted on alternate points
+    fn curve(p: &Params, noise: f64) -> Vec<(f64, f64)> {
+        (0..13)
+            .map(|i| {
+                let x = -9.0 + 0.5 * i as f64;
+                let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+                (x, model(p, x) + sign * noise)
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn recovers_rising_curve() {
+        let points = curve(&[10.0, 100.0, -6.0, 1.5], 0.0);
+        let fit = fit_curve("C1", "Area", &points);
+        assert!(fit.converged);
+        assert_close(fit.bottom.value, 10.0, 1e-6);
+        assert_close(fit.top.value, 100.0, 1e-6);
+        assert_close(fit.ec50.value.log10(), -6.0, 1e-6);
+        assert_close(fit.hill.value, 1.5, 1e-6);
+        assert_close(fit.r_squared, 1.0, 1e-9);
+        assert_eq!((fit.n_points, fit.n_concentrations), (13, 13));
+    }
+
+    #[test]
+    fn recovers_falling_curve_with_noise() {
+        let points = curve(&[5.0, 50.0, -5.0, -1.0], 0.5);
+        let fit = fit_curve("C1", "Area", &points);
+        assert!(fit.converged);
+        assert_close(fit.bottom.value, 5.0, 1.0);
+        assert_close(fit.top.value, 50.0, 1.0);
+        assert_close(fit.ec50.value.log10(), -5.0, 0.1);
+        assert_close(fit.hill.value, -1.0, 0.1);
+        assert!(fit.ec50.lower < fit.ec50.value && fit.ec50.value < fit.ec50.upper);
+    }
+
+    #[test]
+    fn too_few_concentrations() {
+        let points = [(-8.0, 1.0), (-7.0, 2.0), (-7.0, 2.5), (-6.0, 3.0)];
+        let fit = fit_curve("C1", "Area", &points);
+        assert!(!fit.converged);
+        assert!(fit.ec50.value.is_nan());
+        assert_eq!((fit.n_points, fit.n_concentrations), (4, 3));
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use regex::Regex;
+
+/// Name of the file in a search directory that holds the project's plate name pattern
+pub const PLATE_PATTERN_FILE: &str = "plate-pattern.txt";
+
+/// Regex with named capture groups that splits plate names into metadata fields,
+/// e.g. `(?P<barcode>[^_]+)_rep(?P<replicate>\d+)_(?P<cell_line>[^_]+)_(?P<date>\d{8})`.
+///
+/// The pattern is not anchored, so use `^` and `$` to require a match of the whole name.
+#[derive(Debug, Clone)]
+pub struct PlateNamePattern {
+    re: Regex,
+    columns: Vec<Arc<str>>,
+}
+
+impl PlateNamePattern {
+    /// Read the pattern from the first line of a file that is neither blank nor a `#` comment
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("no plate name pattern in {}", path.display()),
+                )
+            })?;
+
+        line.parse().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })
+    }
+
+    /// Names of the capture groups, in the order they appear in the pattern
+    pub fn columns(&self) -> &[Arc<str>] {
+        &self.columns
+    }
+
+    /// Values of each named group for a plate name, or `None` if the name does not match.
+    ///
+    /// Optional groups that did not take part in the match are empty.
+    pub fn fields<'a>(&self, plate: &'a str) -> Option<Vec<&'a str>> {
+        let caps = self.re.captures(plate)?;
+        Some(
+            self.columns
+                .iter()
+                .map(|c| caps.name(c).map_or("", |m| m.as_str()))
+                .collect(),
+        )
+    }
+}
+
+impl FromStr for PlateNamePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(s).map_err(|e| e.to_string())?;
+        let columns = re
+            .capture_names()
+            .flatten()
+            .map(Arc::from)
+            .collect::<Vec<_>>();
+        if columns.is_empty() {
+            return Err(format!("plate name pattern <{s}> has no named groups"));
+        }
+
+        Ok(Self { re, columns })
+    }
+}
+
+/// Template that names the directories between the search root and each datafile,
+/// e.g. `{experiment}/{condition}/{donor}/**`.
+///
+/// Each `/`-separated segment is a `{name}` that captures one directory, a `*` that
+/// skips one directory, `**` that skips any number of directories, or a literal
+/// directory name that must match exactly.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    root: PathBuf,
+    segments: Vec<Segment>,
+    columns: Vec<Arc<str>>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Field(usize),
+    Literal(String),
+    AnyOne,
+    AnyMany,
+}
+
+impl PathTemplate {
+    /// Parse a template for datafiles found under `root`
+    pub fn new<P: Into<PathBuf>>(root: P, template: &str) -> Result<Self, String> {
+        let mut columns: Vec<Arc<str>> = Vec::new();
+        let segments = template
+            .split(['/', '\\'])
+            .filter(|s| !s.is_empty())
+            .map(|s| match s {
+                "*" => Ok(Segment::AnyOne),
+                "**" => Ok(Segment::AnyMany),
+                _ => match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    Some(name) if name.is_empty() || name.contains(['{', '}']) => {
+                        Err(format!("invalid field <{s}> in path template <{template}>"))
+                    }
+                    Some(name) if columns.iter().any(|c| c.as_ref() == name) => Err(format!(
+                        "field <{name}> repeated in path template <{template}>"
+                    )),
+                    Some(name) => {
+                        columns.push(Arc::from(name));
+                        Ok(Segment::Field(columns.len() - 1))
+                    }
+                    None => Ok(Segment::Literal(s.to_string())),
+                },
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if columns.is_empty() {
+            return Err(format!("path template <{template}> has no {{fields}}"));
+        }
+
+        Ok(Self {
+            root: root.into(),
+            segments,
+            columns,
+        })
+    }
+
+    /// Names of the template fields, in the order they appear in the template
+    pub fn columns(&self) -> &[Arc<str>] {
+        &self.columns
+    }
+
+    /// Values of each field for a datafile, or `None` if its directories don't fit the template
+    pub fn fields<'a>(&self, path: &'a Path) -> Option<Vec<&'a str>> {
+        let dirs = path
+            .parent()?
+            .strip_prefix(&self.root)
+            .ok()?
+            .components()
+            .map(|c| match c {
+                Component::Normal(s) => s.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut values = vec![""; self.columns.len()];
+        match_segments(&self.segments, &dirs, &mut values).then_some(values)
+    }
+}
+
+fn match_segments<'a>(segments: &[Segment], dirs: &[&'a str], values: &mut [&'a str]) -> bool {
+    match (segments.split_first(), dirs.split_first()) {
+        (None, _) => dirs.is_empty(),
+        (Some((Segment::AnyMany, rest)), _) => {
+            (0..=dirs.len()).any(|skip| match_segments(rest, &dirs[skip..], values))
+        }
+        (Some(_), None) => false,
+        (Some((seg, rest)), Some((dir, dirs))) => {
+            let ok = match seg {
+                Segment::Field(i) => {
+                    values[*i] = dir;
+                    true
+                }
+                Segment::Literal(lit) => lit == dir,
+                Segment::AnyOne => true,
+                Segment::AnyMany => unreachable!("handled above"),
+            };
+            ok && match_segments(rest, dirs, values)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_data::{cells, utf8, Datafile, TestDir},
+        write::{combine_files_with, CombineOptions},
+    };
+
+    #[test]
+    fn plate_name_fields() {
+        let pat = r"(?P<barcode>[^_]+)_rep(?P<replicate>\d+)(_(?P<line>[^_]+))?"
+            .parse::<PlateNamePattern>()
+            .unwrap();
+        assert_eq!(
+            pat.columns(),
+            ["barcode", "replicate", "line"].map(Arc::from)
+        );
+        assert_eq!(pat.fields("HX1_rep2_HeLa"), Some(vec!["HX1", "2", "HeLa"]));
+        assert_eq!(pat.fields("HX1_rep2"), Some(vec!["HX1", "2", ""]));
+        assert_eq!(pat.fields("HX1"), None);
+
+        assert!("([a-z]+)_(\\d)".parse::<PlateNamePattern>().is_err());
+        assert!("(?P<a>".parse::<PlateNamePattern>().is_err());
+    }
+
+    #[test]
+    fn pattern_file_skips_comments() {
+        let dir = TestDir::new();
+        let path = dir.root.join(PLATE_PATTERN_FILE);
+        fs::write(
+            &path,
+            "# barcode, then date\n\n  ^(?P<barcode>\\w+)_(?P<date>\\d+)$ \n",
+        )
+        .unwrap();
+        let pat = PlateNamePattern::from_file(&path).unwrap();
+        assert_eq!(pat.fields("P1_20260911"), Some(vec!["P1", "20260911"]));
+
+        fs::write(&path, "# nothing yet\n").unwrap();
+        assert!(PlateNamePattern::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn plate_name_columns_in_the_combined_rows() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tCount\n1\t1\t2\n";
+        dir.write("a/well.txt", Datafile::new("HX1_rep2", None, data));
+        dir.write("b/well.txt", Datafile::new("control", None, data));
+
+        let opts = CombineOptions {
+            plate_pattern: Some(
+                "^(?P<barcode>[^_]+)_rep(?P<replicate>\\d+)$"
+                    .parse()
+                    .unwrap(),
+            ),
+            ..CombineOptions::default()
+        };
+        let mut out = Vec::new();
+        let report = combine_files_with(&mut out, &dir.metadata(), &opts).unwrap();
+        let out = utf8(out);
+        let rows = cells(&out);
+        let at = rows[0].iter().position(|&h| h == "barcode").unwrap();
+        assert_eq!(rows[0][at + 1], "replicate");
+        assert_eq!(rows[1][at..at + 2], ["HX1", "2"]);
+        assert_eq!(rows[2][at..at + 2], ["", ""]);
+        assert_eq!(
+            report.unmatched_plates.into_iter().collect::<Vec<_>>(),
+            ["control"]
+        );
+    }
+
+    fn fields(template: &str, path: &str) -> Option<Vec<String>> {
+        let tmpl = PathTemplate::new("/data", template).unwrap();
+        let path = Path::new("/data").join(path).join("nuclei.txt");
+        tmpl.fields(&path)
+            .map(|f| f.into_iter().map(str::to_string).collect())
+    }
+
+    #[test]
+    fn any_many_skips_any_number_of_directories() {
+        let tmpl = "{screen}/**/{plate}";
+        assert_eq!(fields(tmpl, "s1/P1"), Some(vec!["s1".into(), "P1".into()]));
+        assert_eq!(
+            fields(tmpl, "s1/a/b/P1"),
+            Some(vec!["s1".into(), "P1".into()])
+        );
+        assert_eq!(fields(tmpl, "s1"), None);
+        assert_eq!(fields("**/{plate}", "x/y/P2"), Some(vec!["P2".into()]));
+    }
+
+    #[test]
+    fn any_many_backtracks_to_the_last_fit() {
+        let tmpl = "{a}/**/lit/{b}";
+        assert_eq!(
+            fields(tmpl, "A/x/lit/y/lit/B"),
+            Some(vec!["A".into(), "B".into()])
+        );
+        assert_eq!(fields(tmpl, "A/x/y/B"), None);
+    }
+
+    #[test]
+    fn segments_must_use_every_directory() {
+        assert_eq!(fields("{plate}", "s1/P1"), None);
+        assert_eq!(fields("*/{plate}", "s1/P1"), Some(vec!["P1".into()]));
+        assert_eq!(fields("*/{plate}/*", "s1/P1"), None);
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(PathTemplate::new("/data", "*/**").is_err());
+        assert!(PathTemplate::new("/data", "{}/x").is_err());
+        assert!(PathTemplate::new("/data", "{a}/{a}").is_err());
+    }
+
+    #[test]
+    fn path_columns_in_the_combined_rows() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tCount\n1\t1\t2\n";
+        dir.write("exp1/ctrl/d1/well.txt", Datafile::new("P1", None, data));
+        dir.write("loose/well.txt", Datafile::new("P2", None, data));
+
+        let opts = CombineOptions {
+            path_template: Some(
+                PathTemplate::new(&dir.root, "{experiment}/{condition}/*").unwrap(),
+            ),
+            ..CombineOptions::default()
+        };
+        let mut out = Vec::new();
+        let report = combine_files_with(&mut out, &dir.metadata(), &opts).unwrap();
+        let out = utf8(out);
+        let rows = cells(&out);
+        let at = rows[0].iter().position(|&h| h == "experiment").unwrap();
+        assert_eq!(rows[0][at + 1], "condition");
+        assert_eq!(rows[1][at..at + 2], ["exp1", "ctrl"]);
+        assert_eq!(rows[2][at..at + 2], ["", ""]);
+        assert_eq!(report.unmatched_paths, [dir.root.join("loose/well.txt")]);
+    }
+}
@@ -0,0 +1,643 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    matrix::PlateMatrix,
+    utils::escape_xml,
+    well::{PlateFormat, Well},
+};
+
+/// Image file type of a heatmap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(Self::Png),
+            "svg" => Ok(Self::Svg),
+            _ => Err(format!("unknown image format <{s}>, expected png or svg")),
+        }
+    }
+}
+
+/// Values mapped to the ends of the colour scale
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColourRange {
+    /// each plate's own smallest and largest values
+    #[default]
+    PerPlate,
+    /// the same range on every plate, so plates can be compared; values outside are clamped
+    Fixed { min: f64, max: f64 },
+}
+
+impl FromStr for ColourRange {
+    type Err = String;
+
+    /// `plate`, or a fixed range as `MIN,MAX`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("plate") {
+            return Ok(Self::PerPlate);
+        }
+        let err = || format!("invalid colour range <{s}>, expected plate or MIN,MAX");
+        let (min, max) = s.split_once(',').ok_or_else(err)?;
+        let min = min.trim().parse::<f64>().map_err(|_| err())?;
+        let max = max.trim().parse::<f64>().map_err(|_| err())?;
+        if !(min.is_finite() && max.is_finite() && min < max) {
+            return Err(err());
+        }
+        Ok(Self::Fixed { min, max })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HeatmapOptions {
+    pub format: ImageFormat,
+    pub range: ColourRange,
+}
+
+impl HeatmapOptions {
+    fn range_of(&self, matrix: &PlateMatrix) -> (f64, f64) {
+        match self.range {
+            ColourRange::Fixed { min, max } => (min, max),
+            ColourRange::PerPlate => matrix
+                .values
+                .values()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+                    (lo.min(v), hi.max(v))
+                }),
+        }
+    }
+}
+
+/// Render one plate matrix as an image: a cell per well coloured on the viridis
+/// scale, row letters and column numbers, and a colour bar with its range.
+/// Wells without a value are left grey.
+pub fn write_heatmap(
+    wtr: impl Write,
+    matrix: &PlateMatrix,
+    opts: &HeatmapOptions,
+) -> io::Result<()> {
+    let range = opts.range_of(matrix);
+    let heatmap = Heatmap::new(matrix, range);
+    match opts.format {
+        ImageFormat::Png => heatmap.write_png(wtr),
+        ImageFormat::Svg => heatmap.write_svg(wtr),
+    }
+}
+
+/// Write a heatmap file per matrix into `dir`, named by plate and feature,
+/// returning the paths written
+pub fn write_heatmaps(
+    dir: &Path,
+    matrices: &[PlateMatrix],
+    opts: &HeatmapOptions,
+) -> io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    let mut written = Vec::with_capacity(matrices.len());
+    for m in matrices {
+        let path = dir.join(format!("{}.{}", m.file_stem(), opts.format.extension()));
+        let mut wtr = BufWriter::new(File::create(&path)?);
+        write_heatmap(&mut wtr, m, opts)?;
+        wtr.flush()?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+const MISSING: [u8; 3] = [0xd9, 0xd9, 0xd9];
+const INK: [u8; 3] = [0x20, 0x20, 0x20];
+const PAD: u32 = 10;
+const BAR_WIDTH: u32 = 16;
+
+/// Where everything goes on the image, in pixels
+struct Heatmap<'a> {
+    matrix: &'a PlateMatrix,
+    range: (f64, f64),
+    /// range labels for the top, middle and bottom of the colour bar
+    ticks: [String; 3],
+    cell: u32,
+    /// font scale of the 5x7 pixel glyphs
+    scale: u32,
+    grid_x: u32,
+    grid_y: u32,
+    bar_x: u32,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> Heatmap<'a> {
+    fn new(matrix: &'a PlateMatrix, range: (f64, f64)) -> Self {
+        let (cell, scale) = match matrix.format {
+            PlateFormat::Wells96 => (36, 2),
+            PlateFormat::Wells384 => (24, 2),
+            PlateFormat::Wells1536 => (14, 1),
+        };
+        let ticks = [range.1, (range.0 + range.1) / 2.0, range.0].map(tick_label);
+        let char_w = 6 * scale;
+        let line_h = 7 * scale;
+
+        // room for two letter row labels, and a title line above the column numbers
+        let grid_x = PAD + 2 * char_w + 6;
+        let grid_y = PAD + line_h + PAD + line_h + 6;
+        let grid_w = matrix.format.columns() * cell;
+        let grid_h = matrix.format.rows() * cell;
+        let bar_x = grid_x + grid_w + 2 * PAD;
+        let tick_w = ticks.iter().map(|t| t.len() as u32).max().unwrap_or(0) * char_w;
+        let title_w = grid_x + matrix.title().chars().count() as u32 * char_w;
+        let width = (bar_x + BAR_WIDTH + 6 + tick_w).max(title_w) + PAD;
+
+        Self {
+            matrix,
+            range,
+            ticks,
+            cell,
+            scale,
+            grid_x,
+            grid_y,
+            bar_x,
+            width,
+            height: grid_y + grid_h + PAD,
+        }
+    }
+
+    fn rows(&self) -> u32 {
+        self.matrix.format.rows()
+    }
+
+    fn columns(&self) -> u32 {
+        self.matrix.format.columns()
+    }
+
+    /// Smaller column numbers when two digits would not fit over a well
+    fn column_scale(&self) -> u32 {
+        if 12 * self.scale > self.cell - 2 {
+            1
+        } else {
+            self.scale
+        }
+    }
+
+    fn grid_height(&self) -> u32 {
+        self.rows() * self.cell
+    }
+
+    /// Position of a value on the colour scale, from 0 to 1
+    fn scaled(&self, v: f64) -> f64 {
+        let (lo, hi) = self.range;
+        if hi > lo {
+            ((v - lo) / (hi - lo)).clamp(0.0, 1.0)
+        } else {
+            0.5
+        }
+    }
+
+    fn colour(&self, well: Well) -> [u8; 3] {
+        match self.matrix.values.get(&well) {
+            Some(&v) if v.is_finite() => viridis(self.scaled(v)),
+            _ => MISSING,
+        }
+    }
+
+    fn write_svg(&self, mut wtr: impl Write) -> io::Result<()> {
+        let font = 9 * self.scale;
+        let line_h = 7 * self.scale;
+        writeln!(
+            wtr,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="{font}">"#,
+            w = self.width,
+            h = self.height,
+        )?;
+        writeln!(wtr, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+        writeln!(
+            wtr,
+            r#"<text x="{}" y="{}">{}</text>"#,
+            self.grid_x,
+            PAD + line_h,
+            escape_xml(&self.matrix.title())
+        )?;
+
+        let label_y = self.grid_y - 6;
+        for c in 1..=self.columns() {
+            let x = self.grid_x + (c - 1) * self.cell + self.cell / 2;
+            writeln!(
+                wtr,
+                r#"<text x="{x}" y="{label_y}" text-anchor="middle" font-size="{}">{c}</text>"#,
+                9 * self.column_scale()
+            )?;
+        }
+        for r in 1..=self.rows() {
+            let y = self.grid_y + (r - 1) * self.cell + self.cell / 2;
+            writeln!(
+                wtr,
+                r#"<text x="{}" y="{y}" text-anchor="end" dominant-baseline="central">{}</text>"#,
+                self.grid_x - 6,
+                Well::new(r, 1).row_label()
+            )?;
+        }
+
+        for r in 1..=self.rows() {
+            for c in 1..=self.columns() {
+                let well = Well::new(r, c);
+                let [red, green, blue] = self.colour(well);
+                let value = self
+                    .matrix
+                    .get(r, c)
+                    .map_or_else(String::new, |v| format!(": {v}"));
+                writeln!(
+                    wtr,
+                    r##"<rect x="{}" y="{}" width="{s}" height="{s}" fill="#{red:02x}{green:02x}{blue:02x}" stroke="white" stroke-width="0.5"><title>{well}{value}</title></rect>"##,
+                    self.grid_x + (c - 1) * self.cell,
+                    self.grid_y + (r - 1) * self.cell,
+                    s = self.cell,
+                )?;
+            }
+        }
+
+        // colour bar, from the top of the range at the top; the id is unique to the
+        // plate and feature so several heatmaps can share an HTML page
+        let id = format!("scale-{}", self.matrix.file_stem());
+        writeln!(
+            wtr,
+            r#"<defs><linearGradient id="{id}" x1="0" y1="1" x2="0" y2="0">"#
+        )?;
+        for i in 0..=10 {
+            let t = i as f64 / 10.0;
+            let [red, green, blue] = viridis(t);
+            writeln!(
+                wtr,
+                r##"<stop offset="{t}" stop-color="#{red:02x}{green:02x}{blue:02x}"/>"##
+            )?;
+        }
+        writeln!(wtr, "</linearGradient></defs>")?;
+        writeln!(
+            wtr,
+            r#"<rect x="{}" y="{}" width="{BAR_WIDTH}" height="{}" fill="url(#{id})"/>"#,
+            self.bar_x,
+            self.grid_y,
+            self.grid_height()
+        )?;
+        for (i, tick) in self.ticks.iter().enumerate() {
+            let y = self.grid_y + self.grid_height() * i as u32 / 2;
+            writeln!(
+                wtr,
+                r#"<text x="{}" y="{y}" dominant-baseline="central">{}</text>"#,
+                self.bar_x + BAR_WIDTH + 6,
+                escape_xml(tick)
+            )?;
+        }
+        writeln!(wtr, "</svg>")
+    }
+
+    fn write_png(&self, wtr: impl Write) -> io::Result<()> {
+        let mut canvas = Canvas::new(self.width, self.height);
+        let line_h = 7 * self.scale;
+
+        canvas.text(
+            self.grid_x,
+            PAD,
+            &self.matrix.title().to_ascii_uppercase(),
+            self.scale,
+        );
+        let column_scale = self.column_scale();
+        let label_y = self.grid_y - 6 - 7 * column_scale;
+        for c in 1..=self.columns() {
+            let label = c.to_string();
+            let centre = self.grid_x + (c - 1) * self.cell + self.cell / 2;
+            let x = centre.saturating_sub(canvas.text_width(&label, column_scale) / 2);
+            canvas.text(x, label_y, &label, column_scale);
+        }
+        for r in 1..=self.rows() {
+            let label = Well::new(r, 1).row_label();
+            let x = self.grid_x - 6 - canvas.text_width(&label, self.scale);
+            let y = self.grid_y + (r - 1) * self.cell + (self.cell - line_h) / 2;
+            canvas.text(x, y, &label, self.scale);
+        }
+
+        for r in 1..=self.rows() {
+            for c in 1..=self.columns() {
+                let x = self.grid_x + (c - 1) * self.cell;
+                let y = self.grid_y + (r - 1) * self.cell;
+                // a one pixel gap between wells
+                canvas.fill(
+                    x,
+                    y,
+                    self.cell - 1,
+                    self.cell - 1,
+                    self.colour(Well::new(r, c)),
+                );
+            }
+        }
+
+        let bar_h = self.grid_height();
+        for i in 0..bar_h {
+            let t = 1.0 - i as f64 / (bar_h - 1).max(1) as f64;
+            canvas.fill(self.bar_x, self.grid_y + i, BAR_WIDTH, 1, viridis(t));
+        }
+        for (i, tick) in self.ticks.iter().enumerate() {
+            let y = (self.grid_y + bar_h * i as u32 / 2).saturating_sub(line_h / 2);
+            canvas.text(
+                self.bar_x + BAR_WIDTH + 6,
+                y,
+                &tick.to_ascii_uppercase(),
+                self.scale,
+            );
+        }
+
+        canvas.write_png(wtr)
+    }
+}
+
+/// An RGB image, white to begin with
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0xff; (width * height * 3) as usize],
+        }
+    }
+
+    fn fill(&mut self, x: u32, y: u32, w: u32, h: u32, colour: [u8; 3]) {
+        for row in y..(y + h).min(self.height) {
+            for col in x..(x + w).min(self.width) {
+                let i = ((row * self.width + col) * 3) as usize;
+                self.pixels[i..i + 3].copy_from_slice(&colour);
+            }
+        }
+    }
+
+    fn text_width(&self, s: &str, scale: u32) -> u32 {
+        (s.chars().count() as u32 * 6).saturating_sub(1) * scale
+    }
+
+    /// Draw text with its top left corner at `x`, `y`; characters without a glyph are blank
+    fn text(&mut self, x: u32, y: u32, s: &str, scale: u32) {
+        for (i, ch) in s.chars().enumerate() {
+            let left = x + i as u32 * 6 * scale;
+            for (dy, bits) in glyph(ch).iter().enumerate() {
+                for dx in 0..5 {
+                    if bits & (0x10 >> dx) != 0 {
+                        let px = left + dx * scale;
+                        let py = y + dy as u32 * scale;
+                        self.fill(px, py, scale, scale, INK);
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_png(&self, wtr: impl Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(wtr, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut w| w.write_image_data(&self.pixels))
+            .map_err(io::Error::other)
+    }
+}
+
+/// Polynomial fit of matplotlib's viridis colour map, for `t` from 0 to 1
+fn viridis(t: f64) -> [u8; 3] {
+    const C: [[f64; 3]; 7] = [
+        [
+            0.277_727_327_223_417_7,
+            0.005_407_344_544_966_578,
+            0.334_099_805_335_306_1,
+        ],
+        [
+            0.105_093_043_108_577_4,
+            1.404_613_529_898_575,
+            1.384_590_162_594_685,
+        ],
+        [
+            -0.330_861_828_725_556_3,
+            0.214_847_559_468_213,
+            0.095_095_163_028_236_59,
+        ],
+        [
+            -4.634_230_498_983_486,
+            -5.799_100_973_351_585,
+            -19.332_440_956_279_87,
+        ],
+        [
+            6.228_269_936_347_081,
+            14.179_933_366_805_09,
+            56.690_552_600_681_05,
+        ],
+        [
+            4.776_384_997_670_288,
+            -13.745_145_377_746_01,
+            -65.353_032_633_372_34,
+        ],
+        [
+            -5.435_455_855_934_631,
+            4.645_852_612_178_535,
+            26.312_435_249_583_2,
+        ],
+    ];
+    let t = t.clamp(0.0, 1.0);
+    [0, 1, 2].map(|i| {
+        let v = C.iter().rev().fold(0.0, |acc, c| acc * t + c[i]);
+        (v.clamp(0.0, 1.0) * 255.0).round() as u8
+    })
+}
+
+/// Short label for a colour bar value, with about four significant digits
+fn tick_label(v: f64) -> String {
+    if !v.is_finite() {
+        return String::new();
+    }
+    let magnitude = v.abs();
+    if magnitude == 0.0 {
+        return "0".into();
+    }
+    if !(1e-3..1e5).contains(&magnitude) {
+        return format!("{v:.2e}");
+    }
+    let decimals = (3 - magnitude.log10().floor() as i32).clamp(0, 4) as usize;
+    let s = format!("{v:.decimals$}");
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        s
+    }
+}
+
+/// 5x7 pixel glyphs, one row per byte with the leftmost pixel in bit 4
+fn glyph(ch: char) -> [u8; 7] {
+    match ch {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' | '[' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' | ']' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        _ => [0; 7],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn matrix() -> PlateMatrix {
+        PlateMatrix {
+            plate: "P<1>".into(),
+            measurement: None,
+            evaluation: None,
+            feature: "Count".into(),
+            format: PlateFormat::Wells96,
+            values: BTreeMap::from([
+                (Well::new(1, 1), 10.0),
+                (Well::new(1, 2), 20.0),
+                (Well::new(8, 12), 30.0),
+            ]),
+        }
+    }
+
+    #[test]
+    fn colour_ranges() {
+        assert_eq!("Plate".parse(), Ok(ColourRange::PerPlate));
+        assert_eq!(
+            " -1, 2.5".parse(),
+            Ok(ColourRange::Fixed {
+                min: -1.0,
+                max: 2.5
+            })
+        );
+        assert!("2,1".parse::<ColourRange>().is_err());
+        assert!("0,inf".parse::<ColourRange>().is_err());
+        assert!("1".parse::<ColourRange>().is_err());
+    }
+
+    #[test]
+    fn tick_labels() {
+        let labels = [0.0, 1.5, 1234.5678, 0.012345, -20.0, 123456.0, 0.0001].map(tick_label);
+        assert_eq!(
+            labels,
+            ["0", "1.5", "1235", "0.0123", "-20", "1.23e5", "1.00e-4"]
+        );
+        assert_eq!(tick_label(f64::NAN), "");
+    }
+
+    #[test]
+    fn svg_cells_coloured_by_value() {
+        let mut svg = Vec::new();
+        let opts = HeatmapOptions {
+            format: ImageFormat::Svg,
+            range: ColourRange::Fixed {
+                min: 0.0,
+                max: 20.0,
+            },
+        };
+        write_heatmap(&mut svg, &matrix(), &opts).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+
+        assert!(svg.contains("<text x=\"40\" y=\"24\">P&lt;1&gt; - Count</text>"));
+        let fill = |[r, g, b]: [u8; 3]| format!("fill=\"#{r:02x}{g:02x}{b:02x}\"");
+        let cell = |id: &str| {
+            svg.lines()
+                .find(|l| l.contains(&format!("<title>{id}")))
+                .unwrap()
+                .to_string()
+        };
+        assert!(cell("A01: 10").contains(&fill(viridis(0.5))));
+        // values outside a fixed range are clamped
+        assert!(cell("H12: 30").contains(&fill(viridis(1.0))));
+        assert!(cell("B01</title>").contains(&fill(MISSING)));
+        assert_eq!(svg.matches("<rect x=").count(), 96 + 1);
+    }
+
+    #[test]
+    fn png_cells_coloured_by_value() {
+        let matrix = matrix();
+        let mut out = Vec::new();
+        write_heatmap(&mut out, &matrix, &HeatmapOptions::default()).unwrap();
+
+        let mut reader = png::Decoder::new(io::Cursor::new(out)).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        let layout = Heatmap::new(&matrix, (10.0, 30.0));
+        assert_eq!((info.width, info.height), (layout.width, layout.height));
+
+        let pixel = |r: u32, c: u32| {
+            let x = layout.grid_x + (c - 1) * layout.cell + layout.cell / 2;
+            let y = layout.grid_y + (r - 1) * layout.cell + layout.cell / 2;
+            let i = ((y * info.width + x) * 3) as usize;
+            [pixels[i], pixels[i + 1], pixels[i + 2]]
+        };
+        assert_eq!(pixel(1, 1), viridis(0.0));
+        assert_eq!(pixel(1, 2), viridis(0.5));
+        assert_eq!(pixel(8, 12), viridis(1.0));
+        assert_eq!(pixel(2, 1), MISSING);
+    }
+}
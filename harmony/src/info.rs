@@ -1,3 +1,5 @@
+#[cfg(feature = "serde")]
+use std::io::{self, Read, Write};
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
@@ -7,6 +9,7 @@ use crate::utils::{read_lines, StrIntern};
 use walkdir::{DirEntry, WalkDir};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HarmonyMetadata {
     pub path: PathBuf,
     pub db_name: Arc<str>,
@@ -36,6 +39,107 @@ pub fn collect_harmony_datafiles<P: AsRef<Path>>(dir: P) -> Vec<HarmonyMetadata>
     iterate_harmony_datafiles(dir).collect()
 }
 
+/// Harmony datafiles found below a directory, and the text files passed over
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanReport {
+    /// directory that was searched
+    pub root: PathBuf,
+    pub files: Vec<HarmonyMetadata>,
+    /// `.txt` files that could not be read or lack a complete harmony header
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Like [`collect_harmony_datafiles`], but also keep track of the files that were skipped
+pub fn scan_harmony_datafiles<P: AsRef<Path>>(dir: P) -> ScanReport {
+    let mut interner = StrIntern::new();
+    let mut report = ScanReport {
+        root: dir.as_ref().to_path_buf(),
+        ..ScanReport::default()
+    };
+
+    let entries = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok().filter(is_possible_harmony));
+    for e in entries {
+        let collected = read_harmony_metadata(&e, &mut interner);
+        let path = e.into_path();
+        match collected.and_then(|m| m.finalize(path.clone())) {
+            Some(md) => report.files.push(md),
+            None => report.skipped.push(path),
+        }
+    }
+    report
+}
+
+/// Version of the JSON form of a [`ScanReport`], raised whenever its fields change
+#[cfg(feature = "serde")]
+pub const SCAN_JSON_VERSION: u32 = 1;
+
+#[cfg(feature = "serde")]
+impl ScanReport {
+    /// Write the report as JSON, tagged with [`SCAN_JSON_VERSION`]:
+    ///
+    /// ```json
+    /// {
+    ///   "version": 1,
+    ///   "root": "exports",
+    ///   "files": [
+    ///     {
+    ///       "path": "exports/plate 1/Objects_Population - Nuclei.txt",
+    ///       "db_name": "Harmony",
+    ///       "db_location": "//server/harmony",
+    ///       "eval_sig": "a1b2c3",
+    ///       "plate_name": "HX1234",
+    ///       "measurement": 1,
+    ///       "evaluation": 2,
+    ///       "population": "Nuclei",
+    ///       "headers": ["Row", "Column", "Field", "Nuclei - Area [µm²]"],
+    ///       "data_start": 9
+    ///     }
+    ///   ],
+    ///   "skipped": ["exports/readme.txt"]
+    /// }
+    /// ```
+    ///
+    /// `population` is `null` for well-level results, and `data_start` is the line of
+    /// the first data row, counting from 0.
+    pub fn write_json(&self, wtr: impl Write) -> io::Result<()> {
+        #[derive(serde::Serialize)]
+        struct Versioned<'a> {
+            version: u32,
+            #[serde(flatten)]
+            report: &'a ScanReport,
+        }
+        let versioned = Versioned {
+            version: SCAN_JSON_VERSION,
+            report: self,
+        };
+        serde_json::to_writer_pretty(wtr, &versioned)?;
+        Ok(())
+    }
+
+    /// Read a report written by [`write_json`](Self::write_json) with the current
+    /// [`SCAN_JSON_VERSION`]
+    pub fn read_json(rdr: impl Read) -> io::Result<Self> {
+        let mut json: serde_json::Value = serde_json::from_reader(rdr)?;
+        let version = json.get("version").and_then(|v| v.as_u64());
+        if version != Some(SCAN_JSON_VERSION.into()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "scan report version {} is not the supported version {SCAN_JSON_VERSION}",
+                    version.map_or_else(|| "<missing>".to_string(), |v| v.to_string())
+                ),
+            ));
+        }
+        if let Some(obj) = json.as_object_mut() {
+            obj.remove("version");
+        }
+        Ok(serde_json::from_value(json)?)
+    }
+}
+
 fn is_possible_harmony(f: &DirEntry) -> bool {
     f.file_type().is_file() && f.path().extension().map_or(false, |ext| ext == "txt")
 }
@@ -148,3 +252,53 @@ fn process_metadata_kv(
 
     Some(())
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::test_data::{Datafile, TestDir};
+
+    #[test]
+    fn scan_json_round_trip() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tNuclei - Area [µm²]\n1\t1\t5\n";
+        dir.write("p/nuclei.txt", Datafile::new("P1", Some("Nuclei"), data));
+        std::fs::write(dir.root.join("readme.txt"), "not an export\n").unwrap();
+        let scan = scan_harmony_datafiles(&dir.root);
+
+        let mut json = Vec::new();
+        scan.write_json(&mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["version"], SCAN_JSON_VERSION);
+        let file = &value["files"][0];
+        assert_eq!(file["plate_name"], "P1");
+        assert_eq!(file["population"], "Nuclei");
+        assert_eq!(file["headers"][2], "Nuclei - Area [µm²]");
+        assert_eq!(
+            value["skipped"][0],
+            dir.root.join("readme.txt").to_str().unwrap()
+        );
+
+        let read = ScanReport::read_json(json.as_slice()).unwrap();
+        assert_eq!(read.root, scan.root);
+        assert_eq!(read.skipped, scan.skipped);
+        let (a, b) = (&read.files[0], &scan.files[0]);
+        assert_eq!(
+            (&a.path, &a.population, &a.headers),
+            (&b.path, &b.population, &b.headers)
+        );
+        assert_eq!((a.measurement, a.data_start), (b.measurement, b.data_start));
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let newer = r#"{"version": 2, "root": "x", "files": [], "skipped": []}"#;
+        let err = ScanReport::read_json(newer.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("version 2"));
+
+        let unversioned = r#"{"root": "x", "files": [], "skipped": []}"#;
+        let err = ScanReport::read_json(unversioned.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("<missing>"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::{
+    info::HarmonyMetadata,
+    rows::Value,
+    sink::RecordSink,
+    write::{combine_into, CombineOptions, CombineReport, TEXT_FIELD_HDRS},
+};
+
+/// Write the combined data as JSON Lines, see [`JsonlSink`]
+pub fn write_jsonl(
+    wtr: impl Write,
+    metadata: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    combine_into(JsonlSink::new(wtr), metadata, opts)
+}
+
+/// JSON Lines, one object per row with a key for every column.
+///
+/// Numbers are written as JSON numbers, except ones with leading zeros, and empty or
+/// non-finite values as `null`. The plate name, evaluation signature and population
+/// are always strings.
+#[derive(Debug)]
+pub struct JsonlSink<W> {
+    wtr: W,
+    /// `"key":` of each column
+    keys: Vec<String>,
+    /// columns written as strings whatever they hold
+    text: Vec<bool>,
+    line: String,
+}
+
+impl<W: Write> JsonlSink<W> {
+    pub fn new(wtr: W) -> Self {
+        Self {
+            wtr,
+            keys: Vec::new(),
+            text: Vec::new(),
+            line: String::with_capacity(0x400),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+}
+
+impl<W: Write> RecordSink for JsonlSink<W> {
+    fn begin_schema(&mut self, header: &[&str]) -> io::Result<()> {
+        self.keys = header
+            .iter()
+            .map(|k| {
+                let mut key = String::new();
+                push_str(&mut key, k);
+                key.push(':');
+                key
+            })
+            .collect();
+        self.text = header.iter().map(|k| TEXT_FIELD_HDRS.contains(k)).collect();
+        Ok(())
+    }
+
+    fn write_row(&mut self, cells: &[&str]) -> io::Result<()> {
+        self.line.clear();
+        self.line.push('{');
+        for (i, (key, v)) in self.keys.iter().zip(cells).enumerate() {
+            if i > 0 {
+                self.line.push(',');
+            }
+            self.line.push_str(key);
+            if self.text[i] && !v.is_empty() {
+                push_str(&mut self.line, v);
+            } else {
+                push_value(&mut self.line, v);
+            }
+        }
+        self.line.push('}');
+        writeln!(self.wtr, "{}", self.line)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+/// Append a cell as a JSON number, string or `null`
+fn push_value(out: &mut String, v: &str) {
+    match Value::parse(v) {
+        Value::Integer(n) => {
+            let _ = write!(out, "{n}");
+        }
+        Value::Real(n) if n.is_finite() => {
+            let _ = write!(out, "{n}");
+        }
+        Value::Null | Value::Real(_) => out.push_str("null"),
+        Value::Text(s) => push_str(out, s),
+    }
+}
+
+/// Append a JSON string literal
+fn push_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::utf8;
+
+    #[test]
+    fn cells_typed_by_what_they_hold() {
+        let mut sink = JsonlSink::new(Vec::new());
+        let header = ["Plate Name", "Population", "Count", "Area", "Code", "Note"];
+        sink.begin_schema(&header).unwrap();
+        sink.write_row(&["007", "Nuc\"lei", "12", "2.5", "007", "a\tb"])
+            .unwrap();
+        sink.write_row(&["", "Well", "-3", "NaN", "", "1e3"])
+            .unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(
+            utf8(sink.into_inner()),
+            "{\"Plate Name\":\"007\",\"Population\":\"Nuc\\\"lei\",\"Count\":12,\"Area\":2.5,\"Code\":\"007\",\"Note\":\"a\\tb\"}\n\
+             {\"Plate Name\":null,\"Population\":\"Well\",\"Count\":-3,\"Area\":null,\"Code\":null,\"Note\":1000}\n"
+        );
+    }
+
+    #[test]
+    fn control_characters_escaped() {
+        let mut out = String::new();
+        push_str(&mut out, "a\u{1}\\\nb");
+        assert_eq!(out, "\"a\\u0001\\\\\\nb\"");
+    }
+}
@@ -1,8 +1,83 @@
+mod aggregate;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod bscore;
+mod concordance;
+#[cfg(feature = "serde")]
+mod de;
+mod dose;
+mod fields;
+mod heatmap;
 mod info;
+mod jsonl;
+mod long;
+mod matrix;
+mod normalise;
+mod plan;
+mod platemap;
+mod qc;
+mod report;
+mod rows;
+mod sink;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
+mod table;
+#[cfg(test)]
+mod test_data;
+mod timecourse;
 mod utils;
+mod well;
+mod wide;
 mod write;
+#[cfg(feature = "xlsx")]
+mod xlsx;
 
 pub use crate::{
-    info::{collect_harmony_datafiles, iterate_harmony_datafiles, HarmonyMetadata},
-    write::combine_files,
+    aggregate::{AggregateOptions, Statistic},
+    bscore::{b_score, BScoreOptions},
+    concordance::{
+        replicate_concordance, ConcordanceOptions, ConcordanceReport, FlaggedWell,
+        ReplicateGrouping, ReplicatePair,
+    },
+    dose::{
+        fit_dose_response, write_dose_response, DoseResponseFit, DoseResponseOptions, Estimate,
+    },
+    fields::{PathTemplate, PlateNamePattern, PLATE_PATTERN_FILE},
+    heatmap::{write_heatmap, write_heatmaps, ColourRange, HeatmapOptions, ImageFormat},
+    info::{
+        collect_harmony_datafiles, iterate_harmony_datafiles, scan_harmony_datafiles,
+        HarmonyMetadata, ScanReport,
+    },
+    jsonl::{write_jsonl, JsonlSink},
+    matrix::{plate_matrices, write_plate_matrices, PlateMatrix},
+    normalise::{normalise, NormMethod, NormaliseOptions, NormaliseReport},
+    plan::{plan_combine, ColumnSource, CombinePlan, FilePlan},
+    platemap::{
+        write_plate_map_template, AnnotationReport, ControlKind, Controls, PlateMap,
+        DEFAULT_TEMPLATE_COLUMNS,
+    },
+    qc::{plate_qc, PlateQc, QcFlag, QcOptions, QcReport, QcThresholds},
+    report::RunReport,
+    rows::{Row, Rows, Value},
+    sink::{RecordSink, TsvSink},
+    table::{combine_table, Table},
+    timecourse::{parse_interval, time_course, TimeCourseOptions, TimeSource},
+    well::{collect_plate_wells, detect_plate_formats, PlateFormat, Well, WellStyle},
+    write::{
+        combine_files, combine_files_with, combine_into, CombineOptions, CombineReport, Layout,
+        WellOptions,
+    },
 };
+
+#[cfg(feature = "arrow")]
+pub use crate::arrow::{write_arrow, ArrowSink};
+
+#[cfg(feature = "serde")]
+pub use crate::info::SCAN_JSON_VERSION;
+
+#[cfg(feature = "sqlite")]
+pub use crate::sqlite::write_sqlite;
+
+#[cfg(feature = "xlsx")]
+pub use crate::xlsx::write_xlsx;
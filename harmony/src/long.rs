@@ -0,0 +1,153 @@
+use std::{io, sync::Arc};
+
+use crate::{
+    aggregate::ID_HDRS,
+    info::HarmonyMetadata,
+    rows::Rows,
+    sink::RecordSink,
+    utils::column_index,
+    write::{CombineOptions, CombineReport, MetaColumns},
+};
+
+const LONG_HDRS: &[&str] = &["Feature", "Value", "Unit"];
+
+/// Split a trailing unit off a harmony column name, e.g. `Nuclei - Area [µm²]`
+fn split_unit(name: &str) -> (&str, &str) {
+    match name.strip_suffix(']').and_then(|n| n.rsplit_once(" [")) {
+        Some((feature, unit)) => (feature, unit),
+        None => (name, ""),
+    }
+}
+
+/// Write one row per feature value: the metadata and ID columns (`Row`, `Column`,
+/// `Field`, ...), then `Feature`, `Value` and `Unit`.
+///
+/// Files are streamed a line at a time, so nothing is held in memory but the
+/// current line; empty cells are left out.
+pub(crate) fn write_long(
+    sink: &mut impl RecordSink,
+    md: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    let mut report = CombineReport::default();
+
+    let ids = ID_HDRS
+        .iter()
+        .copied()
+        .filter(|h| md.iter().any(|m| column_index(&m.headers, h).is_some()))
+        .collect::<Vec<_>>();
+    let id_hdr = ids.iter().map(|&h| Arc::from(h)).collect::<Vec<Arc<str>>>();
+    let meta = MetaColumns::new(md, opts, &id_hdr)?;
+
+    let mut header = meta.header(true);
+    header.extend(&ids);
+    header.extend(LONG_HDRS);
+    sink.begin_schema(&header)?;
+
+    for m in md {
+        let feature_hdr = m
+            .headers
+            .iter()
+            .filter(|h| !ids.contains(&h.as_ref()))
+            .collect::<Vec<_>>();
+        let features = feature_hdr
+            .iter()
+            .map(|h| split_unit(h))
+            .collect::<Vec<_>>();
+        let common_info = meta.file_values(m, true, &mut report);
+
+        let columns = id_hdr.iter().chain(feature_hdr).cloned().collect();
+        let mut rows = Rows::with_header([m], columns);
+        let mut n = 0;
+        while let Some(row) = rows.next_row() {
+            let row = row?;
+            n += 1;
+            let id_values = (0..ids.len()).map(|i| row.cell(i)).collect::<Vec<_>>();
+            let well = meta.well(&id_values);
+            let well_values = meta.well_values(&m.plate_name, well, &mut report);
+
+            // the fields repeated on every feature row of this line
+            let mut out = common_info
+                .iter()
+                .chain(&well_values)
+                .map(String::as_str)
+                .chain(id_values)
+                .collect::<Vec<_>>();
+            let prefix = out.len();
+
+            for (i, &(feature, unit)) in features.iter().enumerate() {
+                match row.get(ids.len() + i) {
+                    Some(value) if !value.is_empty() => {
+                        out.truncate(prefix);
+                        out.extend([feature, value, unit]);
+                        sink.write_row(&out)?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        report.add_rows(m, n);
+        sink.end_file(m)?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_data::{cells, utf8, Datafile, TestDir},
+        write::{combine_files_with, Layout},
+    };
+
+    #[test]
+    fn units_split_off() {
+        assert_eq!(split_unit("Nuclei - Area [µm²]"), ("Nuclei - Area", "µm²"));
+        assert_eq!(split_unit("Cell Count"), ("Cell Count", ""));
+        assert_eq!(split_unit("Ratio [a] b"), ("Ratio [a] b", ""));
+    }
+
+    #[test]
+    fn one_row_per_value() {
+        let dir = TestDir::new();
+        let well = "Row\tColumn\tCount\tMean Area [µm²]\n1\t1\t2\t\n1\t2\t3\t40.5\n";
+        let well = dir.write("p/well.txt", Datafile::new("P1", None, well));
+        let nuclei = "Row\tColumn\tObject No\tArea\n1\t1\t1\t5\n";
+        dir.write("p/nuclei.txt", Datafile::new("P1", Some("Nuclei"), nuclei));
+
+        let opts = CombineOptions {
+            layout: Layout::Long,
+            ..CombineOptions::default()
+        };
+        let mut out = Vec::new();
+        let report = combine_files_with(&mut out, &dir.metadata(), &opts).unwrap();
+        let out = utf8(out);
+        let rows = cells(&out);
+        // the common fields, then the ID columns any file has
+        assert_eq!(
+            rows[0][4..],
+            [
+                "Population",
+                "Row",
+                "Column",
+                "Object No",
+                "Feature",
+                "Value",
+                "Unit"
+            ]
+        );
+        let values = rows[1..].iter().map(|r| &r[4..]).collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                &["Nuclei", "1", "1", "1", "Area", "5", ""][..],
+                &["Well", "1", "1", "", "Count", "2", ""][..],
+                &["Well", "1", "2", "", "Count", "3", ""][..],
+                &["Well", "1", "2", "", "Mean Area", "40.5", "µm²"][..],
+            ]
+        );
+        // rows are counted as read, not as written
+        assert_eq!(report.rows[&well], 2);
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+
+use crate::{
+    stats::{self, parse_value},
+    table::{missing_column, Table, PLATE_HDR},
+    well::{PlateFormat, Well},
+};
+
+const MEASUREMENT_HDR: &str = "Measurement";
+const EVALUATION_HDR: &str = "Evaluation";
+
+/// One feature of one plate laid out in plate rows and columns
+#[derive(Debug, Clone)]
+pub struct PlateMatrix {
+    pub plate: String,
+    /// set when the plate was measured more than once
+    pub measurement: Option<String>,
+    /// set when the plate was evaluated more than once
+    pub evaluation: Option<String>,
+    pub feature: String,
+    pub format: PlateFormat,
+    /// mean of each well's values, for wells that have any
+    pub values: BTreeMap<Well, f64>,
+}
+
+impl PlateMatrix {
+    /// Name of the block, e.g. `Plate1 - Cells - Area` or
+    /// `Plate1 (measurement 2, evaluation 1) - ...`
+    pub fn title(&self) -> String {
+        let run = [
+            self.measurement
+                .as_ref()
+                .map(|m| format!("measurement {m}")),
+            self.evaluation.as_ref().map(|e| format!("evaluation {e}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        if run.is_empty() {
+            format!("{} - {}", self.plate, self.feature)
+        } else {
+            format!("{} ({}) - {}", self.plate, run.join(", "), self.feature)
+        }
+    }
+
+    /// File name (without extension) for the block, safe on any file system
+    pub fn file_stem(&self) -> String {
+        let mut name = self.plate.clone();
+        if let Some(m) = &self.measurement {
+            name.push_str(&format!("_m{m}"));
+        }
+        if let Some(e) = &self.evaluation {
+            name.push_str(&format!("_e{e}"));
+        }
+        name.push_str(&format!("_{}", self.feature));
+        name.chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect()
+    }
+
+    pub fn get(&self, row: u32, column: u32) -> Option<f64> {
+        self.values.get(&Well::new(row, column)).copied()
+    }
+
+    /// Write the grid: a header of column numbers, then one line per row letter
+    pub fn write_grid(&self, mut wtr: impl Write) -> io::Result<()> {
+        for c in 1..=self.format.columns() {
+            write!(wtr, "\t{c}")?;
+        }
+        writeln!(wtr)?;
+        for r in 1..=self.format.rows() {
+            write!(wtr, "{}", Well::new(r, 1).row_label())?;
+            for c in 1..=self.format.columns() {
+                match self.get(r, c) {
+                    Some(v) => write!(wtr, "\t{v}")?,
+                    None => write!(wtr, "\t")?,
+                }
+            }
+            writeln!(wtr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write every matrix as a block under its title, with blank lines between blocks
+pub fn write_plate_matrices(mut wtr: impl Write, matrices: &[PlateMatrix]) -> io::Result<()> {
+    for (i, m) in matrices.iter().enumerate() {
+        if i > 0 {
+            writeln!(wtr)?;
+        }
+        writeln!(wtr, "{}", m.title())?;
+        m.write_grid(&mut wtr)?;
+    }
+    Ok(())
+}
+
+/// Lay out each feature of each plate (and measurement and evaluation) as a
+/// plate-shaped grid.
+///
+/// Meant for well-level data: rows of the same well, such as fields or timepoints,
+/// are averaged. The grid is the smallest plate format that holds the plate's wells,
+/// unless `format` is given.
+pub fn plate_matrices(
+    table: &Table,
+    features: &[String],
+    format: Option<PlateFormat>,
+) -> io::Result<Vec<PlateMatrix>> {
+    let locator = table.locator()?;
+    let measurement_col = table.column(MEASUREMENT_HDR);
+    let evaluation_col = table.column(EVALUATION_HDR);
+    let feature_cols = features
+        .iter()
+        .map(|f| table.column(f).ok_or_else(|| missing_column(f)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // populations of a stacked table share a grid, as a feature only has values in its own
+    let groups = table.group_by(&[PLATE_HDR, MEASUREMENT_HDR, EVALUATION_HDR]);
+    let run = |row| {
+        let cell = |col: Option<usize>| col.map_or("", |c| table.cell(row, c));
+        (cell(measurement_col), cell(evaluation_col))
+    };
+    let mut runs: BTreeMap<&str, (BTreeSet<&str>, BTreeSet<&str>)> = BTreeMap::new();
+    for rows in groups.values() {
+        let (measurement, evaluation) = run(rows[0]);
+        let plate = runs.entry(locator.plate(table, rows[0])).or_default();
+        plate.0.insert(measurement);
+        plate.1.insert(evaluation);
+    }
+
+    let mut matrices = Vec::new();
+    for rows in groups.values() {
+        let plate = locator.plate(table, rows[0]);
+        let (measurement, evaluation) = run(rows[0]);
+        let (measurements, evaluations) = &runs[plate];
+
+        let mut wells: BTreeMap<Well, Vec<usize>> = BTreeMap::new();
+        for &row in rows {
+            if let Some(well) = locator.well(table, row) {
+                wells.entry(well).or_default().push(row);
+            }
+        }
+        let fitted = match format.or_else(|| PlateFormat::fitting(wells.keys())) {
+            Some(f) => f,
+            None => continue,
+        };
+        let in_format = wells
+            .iter()
+            .filter(|(w, _)| fitted.contains(**w))
+            .collect::<Vec<_>>();
+        for (feature, &col) in features.iter().zip(&feature_cols) {
+            let rows = in_format.iter().flat_map(|(_, rows)| rows.iter().copied());
+            if !table.has_feature(col, rows) {
+                continue;
+            }
+            let values = in_format
+                .iter()
+                .filter_map(|(w, rows)| {
+                    let vals = rows
+                        .iter()
+                        .filter_map(|&r| parse_value(table.cell(r, col)))
+                        .collect::<Vec<_>>();
+                    (!vals.is_empty()).then(|| (**w, stats::mean(&vals)))
+                })
+                .collect::<BTreeMap<_, _>>();
+            matrices.push(PlateMatrix {
+                plate: plate.to_string(),
+                measurement: (measurements.len() > 1).then(|| measurement.to_string()),
+                evaluation: (evaluations.len() > 1).then(|| evaluation.to_string()),
+                feature: feature.clone(),
+                format: fitted,
+                values,
+            });
+        }
+    }
+
+    Ok(matrices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        table::combine_table,
+        test_data::{Datafile, TestDir},
+        write::CombineOptions,
+    };
+
+    #[test]
+    fn grids_of_each_evaluation() {
+        let dir = TestDir::new();
+        let well = "Row\tColumn\tCount\n1\t1\t2\n1\t2\t3\n";
+        dir.write("p1/e1/well.txt", Datafile::new("P1", None, well));
+        let nuclei = "Row\tColumn\tArea\n1\t1\t5\n1\t1\t6\n";
+        dir.write(
+            "p1/e1/nuclei.txt",
+            Datafile::new("P1", Some("Nuclei"), nuclei),
+        );
+        let again = Datafile {
+            evaluation: 2,
+            ..Datafile::new("P1", None, "Row\tColumn\tCount\n1\t1\t4\n")
+        };
+        dir.write("p1/e2/well.txt", again);
+        dir.write("p2/well.txt", Datafile::new("P2", None, well));
+
+        let (table, _) = combine_table(&dir.metadata(), &CombineOptions::default()).unwrap();
+        let features = ["Count".to_string(), "Area".to_string()];
+        let matrices = plate_matrices(&table, &features, None).unwrap();
+        let titles = matrices.iter().map(PlateMatrix::title).collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            [
+                "P1 (evaluation 1) - Count",
+                "P1 (evaluation 1) - Area",
+                "P1 (evaluation 2) - Count",
+                "P2 - Count",
+            ]
+        );
+        assert_eq!(matrices[2].file_stem(), "P1_e2_Count");
+
+        // the nuclei of a well are averaged, and the well population has no area
+        let area = &matrices[1];
+        assert_eq!(area.format, PlateFormat::Wells96);
+        assert_eq!(area.values.len(), 1);
+        assert_eq!(area.get(1, 1), Some(5.5));
+        assert_eq!(matrices[2].values.len(), 1);
+        assert_eq!(matrices[2].get(1, 1), Some(4.0));
+
+        let mut out = Vec::new();
+        write_plate_matrices(&mut out, &matrices[3..]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "P2 - Count");
+        assert!(lines[1].starts_with("\t1\t2\t3\t"));
+        assert!(lines[2].starts_with("A\t2\t3\t\t"));
+        assert_eq!(lines.len(), 2 + 8);
+    }
+}
@@ -0,0 +1,278 @@
+use std::{collections::BTreeSet, fmt, io, str::FromStr};
+
+use crate::{
+    platemap::{ControlKind, Controls, PlateMap},
+    stats::{self, parse_value, MAD_SCALE},
+    table::{missing_column, Table, PLATE_GROUP_HDRS, TIMEPOINT_HDR},
+};
+
+/// Normalise features per plate against its control wells
+#[derive(Debug, Clone)]
+pub struct NormaliseOptions {
+    /// columns to normalise
+    pub features: Vec<String>,
+    pub methods: Vec<NormMethod>,
+    pub controls: Controls,
+    /// normalise each timepoint of a plate against its own controls
+    pub per_timepoint: bool,
+}
+
+impl Default for NormaliseOptions {
+    fn default() -> Self {
+        Self {
+            features: Vec::new(),
+            methods: vec![NormMethod::PercentOfControl],
+            controls: Controls::default(),
+            per_timepoint: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormMethod {
+    /// 100 × value / mean of the negative controls
+    PercentOfControl,
+    /// normalised percent inhibition, 0 at the negative and 100 at the positive control mean
+    PercentInhibition,
+    /// (value − negative mean) / negative standard deviation
+    ZScore,
+    /// (value − negative median) / scaled negative MAD
+    RobustZ,
+}
+
+impl NormMethod {
+    /// every method uses the negative controls, only NPI also needs the positive ones
+    fn needs_positive(self) -> bool {
+        self == Self::PercentInhibition
+    }
+}
+
+impl fmt::Display for NormMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PercentOfControl => write!(f, "PoC"),
+            Self::PercentInhibition => write!(f, "NPI"),
+            Self::ZScore => write!(f, "Z"),
+            Self::RobustZ => write!(f, "Robust Z"),
+        }
+    }
+}
+
+impl FromStr for NormMethod {
+    type Err = String;
+
+    /// Parse `poc`, `npi`, `z` or `robust-z`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "poc" | "percent-of-control" => Ok(Self::PercentOfControl),
+            "npi" | "percent-inhibition" => Ok(Self::PercentInhibition),
+            "z" | "zscore" | "z-score" => Ok(Self::ZScore),
+            "robust-z" | "robustz" | "rz" => Ok(Self::RobustZ),
+            _ => Err(format!(
+                "unknown normalisation <{s}>, expected poc, npi, z or robust-z"
+            )),
+        }
+    }
+}
+
+/// Plates whose controls were missing, so their normalised values were left empty
+#[derive(Debug, Clone, Default)]
+pub struct NormaliseReport {
+    /// plate names, with the timepoint when normalising per timepoint
+    pub missing_negative: BTreeSet<(String, Option<String>)>,
+    /// only checked when a method needs positive controls
+    pub missing_positive: BTreeSet<(String, Option<String>)>,
+}
+
+impl NormaliseReport {
+    pub fn is_empty(&self) -> bool {
+        self.missing_negative.is_empty() && self.missing_positive.is_empty()
+    }
+}
+
+/// Summary of a group's control values for one feature
+struct Reference {
+    neg_mean: f64,
+    neg_sd: f64,
+    neg_median: f64,
+    neg_mad: f64,
+    pos_mean: f64,
+}
+
+impl Reference {
+    fn new(neg: &[f64], pos: &[f64]) -> Self {
+        Self {
+            neg_mean: stats::mean(neg),
+            neg_sd: stats::std_dev(neg),
+            neg_median: stats::median(neg),
+            neg_mad: stats::mad(neg) * MAD_SCALE,
+            pos_mean: stats::mean(pos),
+        }
+    }
+
+    fn apply(&self, method: NormMethod, x: f64) -> f64 {
+        match method {
+            NormMethod::PercentOfControl => 100.0 * x / self.neg_mean,
+            NormMethod::PercentInhibition => {
+                100.0 * (self.neg_mean - x) / (self.neg_mean - self.pos_mean)
+            }
+            NormMethod::ZScore => (x - self.neg_mean) / self.neg_sd,
+            NormMethod::RobustZ => (x - self.neg_median) / self.neg_mad,
+        }
+    }
+}
+
+/// Add normalised columns for each feature, right after its raw column.
+///
+/// Rows are grouped by plate (and measurement, evaluation and population when the
+/// table has those columns, plus timepoint if asked), and each group is normalised
+/// against the control wells the plate map marks in it. Values that can't be
+/// normalised, because they or the controls are missing, are left empty.
+pub fn normalise(
+    table: &mut Table,
+    plate_map: &PlateMap,
+    opts: &NormaliseOptions,
+) -> io::Result<NormaliseReport> {
+    let mut report = NormaliseReport::default();
+    let kinds = opts.controls.classify_rows(table, plate_map)?;
+    let locator = table.locator()?;
+
+    let mut group_hdrs = PLATE_GROUP_HDRS.to_vec();
+    if opts.per_timepoint {
+        table
+            .column(TIMEPOINT_HDR)
+            .ok_or_else(|| missing_column(TIMEPOINT_HDR))?;
+        group_hdrs.push(TIMEPOINT_HDR);
+    }
+    let timepoint = table.column(TIMEPOINT_HDR).filter(|_| opts.per_timepoint);
+
+    let groups = table
+        .group_by(&group_hdrs)
+        .into_values()
+        .collect::<Vec<_>>();
+
+    for feature in &opts.features {
+        let col = table
+            .column(feature)
+            .ok_or_else(|| missing_column(feature))?;
+        let values = (0..table.rows.len())
+            .map(|row| parse_value(table.cell(row, col)))
+            .collect::<Vec<_>>();
+
+        let needs_pos = opts.methods.iter().any(|m| m.needs_positive());
+        let mut columns = vec![vec![String::new(); table.rows.len()]; opts.methods.len()];
+        for rows in &groups {
+            if !table.has_feature(col, rows.iter().copied()) {
+                continue;
+            }
+            let controls = |kind| {
+                rows.iter()
+                    .filter(|&&r| kinds[r] == Some(kind))
+                    .filter_map(|&r| values[r])
+                    .collect::<Vec<_>>()
+            };
+            let neg = controls(ControlKind::Negative);
+            let pos = controls(ControlKind::Positive);
+
+            let group_name = || {
+                let row = rows[0];
+                (
+                    locator.plate(table, row).to_string(),
+                    timepoint.map(|t| table.cell(row, t).to_string()),
+                )
+            };
+            if neg.is_empty() {
+                report.missing_negative.insert(group_name());
+            }
+            if pos.is_empty() && needs_pos {
+                report.missing_positive.insert(group_name());
+            }
+
+            let reference = Reference::new(&neg, &pos);
+            for (method, out) in opts.methods.iter().zip(&mut columns) {
+                for &r in rows {
+                    let v = values[r].map_or(f64::NAN, |x| reference.apply(*method, x));
+                    if v.is_finite() {
+                        out[r] = v.to_string();
+                    }
+                }
+            }
+        }
+
+        for (i, (method, values)) in opts.methods.iter().zip(columns).enumerate() {
+            table.insert_column(col + 1 + i, &format!("{feature} - {method}"), values);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{table, TestDir};
+
+    #[test]
+    fn against_the_controls_of_each_plate() {
+        let dir = TestDir::new();
+        let map = dir.plate_map("Well,Control\nA01,neg\nA02,NEG\nA03,pos\n");
+        let mut table = table(
+            "Plate Name\tPopulation\tRow\tColumn\tCount\tArea\n\
+             P1\tWell\t1\t1\t10\t\n\
+             P1\tWell\t1\t2\t30\t\n\
+             P1\tWell\t1\t3\t0\t\n\
+             P1\tWell\t1\t4\t15\t\n\
+             P1\tWell\t1\t5\t\t\n\
+             P1\tNuclei\t1\t1\t\t7\n\
+             P2\tWell\t1\t1\t\t\n\
+             P2\tWell\t2\t1\t8\t\n",
+        );
+        let opts = NormaliseOptions {
+            features: vec!["Count".into()],
+            methods: "poc,npi,z".split(',').map(|s| s.parse().unwrap()).collect(),
+            ..NormaliseOptions::default()
+        };
+        let report = normalise(&mut table, &map, &opts).unwrap();
+
+        assert_eq!(
+            table.header[4..8],
+            ["Count", "Count - PoC", "Count - NPI", "Count - Z"].map(Into::into)
+        );
+        let z = -5.0 / 200f64.sqrt();
+        assert_eq!(table.rows[3][4..8], ["15", "75", "25", &z.to_string()]);
+        assert_eq!(table.rows[2][4..7], ["0", "0", "100"]);
+        // no value, and rows of a population without the feature
+        assert_eq!(table.rows[4][5..8], ["", "", ""]);
+        assert_eq!(table.rows[5][5..8], ["", "", ""]);
+        // P2's A01 control has no value, so the plate has no controls to go by
+        assert_eq!(table.rows[7][5..8], ["", "", ""]);
+
+        let p2 = BTreeSet::from([("P2".to_string(), None)]);
+        assert_eq!(report.missing_negative, p2);
+        assert_eq!(report.missing_positive, p2);
+    }
+
+    #[test]
+    fn per_timepoint_needs_the_column() {
+        let dir = TestDir::new();
+        let map = dir.plate_map("Well,Control\nA01,neg\n");
+        let mut table = table(
+            "Plate Name\tRow\tColumn\tTimepoint\tCount\n\
+             P1\t1\t1\t0\t10\n\
+             P1\t1\t2\t0\t5\n\
+             P1\t1\t1\t1\t20\n\
+             P1\t1\t2\t1\t5\n",
+        );
+        let opts = NormaliseOptions {
+            features: vec!["Count".into()],
+            per_timepoint: true,
+            ..NormaliseOptions::default()
+        };
+        normalise(&mut table, &map, &opts).unwrap();
+        let poc = table.rows.iter().map(|r| r[5].as_str()).collect::<Vec<_>>();
+        assert_eq!(poc, ["100", "50", "100", "25"]);
+
+        table.header[3] = "Time".into();
+        assert!(normalise(&mut table, &map, &opts).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::{collections::BTreeMap, io, path::PathBuf, sync::Arc};
+
+use crate::{
+    info::HarmonyMetadata,
+    utils::count_rows,
+    write::{combined_header, CombineOptions, Layout, MetaColumns},
+};
+
+/// What [`combine_files_with`](crate::combine_files_with) would write, worked out
+/// without writing it
+#[derive(Debug, Clone, Default)]
+pub struct CombinePlan {
+    /// every output column: the metadata columns, then the data columns
+    pub header: Vec<Arc<str>>,
+    /// the data columns, with the files that have each one
+    pub columns: Vec<ColumnSource>,
+    /// the files, in the order they are written
+    pub files: Vec<FilePlan>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnSource {
+    pub name: Arc<str>,
+    /// positions in [`CombinePlan::files`] of the files with this column
+    pub files: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FilePlan {
+    pub path: PathBuf,
+    /// non-empty data rows, each written as one output row
+    pub rows: usize,
+    /// data columns the file doesn't have, left empty in its rows
+    pub missing: Vec<Arc<str>>,
+}
+
+impl CombinePlan {
+    /// Rows of the combined output, not counting the header
+    pub fn rows(&self) -> usize {
+        self.files.iter().map(|f| f.rows).sum()
+    }
+}
+
+/// Reconcile the headers of `metadata` and count the rows of every file, as combining
+/// them with the stacked layout would, but without writing any output.
+///
+/// Every file is read to count its rows. Other layouts and aggregation are not planned.
+pub fn plan_combine(
+    metadata: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombinePlan> {
+    if opts.layout != Layout::Stacked || opts.aggregate.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only the stacked layout can be planned",
+        ));
+    }
+    let hdr = combined_header(metadata);
+    // no rows are written, so plate formats aren't needed
+    let meta = MetaColumns::with_formats(opts, &hdr, BTreeMap::new())?;
+
+    let mut header = meta
+        .header(true)
+        .into_iter()
+        .map(Arc::from)
+        .collect::<Vec<Arc<str>>>();
+    header.extend(hdr.iter().cloned());
+
+    let columns = hdr
+        .iter()
+        .map(|h| ColumnSource {
+            name: Arc::clone(h),
+            files: (0..metadata.len())
+                .filter(|&i| metadata[i].headers.contains(h))
+                .collect(),
+        })
+        .collect();
+    let files = metadata
+        .iter()
+        .map(|m| {
+            Ok(FilePlan {
+                path: m.path.clone(),
+                rows: count_rows(m)?,
+                missing: hdr
+                    .iter()
+                    .filter(|h| !m.headers.contains(h))
+                    .cloned()
+                    .collect(),
+            })
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(CombinePlan {
+        header,
+        columns,
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_data::{cells, utf8, Datafile, TestDir},
+        write::combine_files_with,
+    };
+
+    #[test]
+    fn plan_matches_the_combined_output() {
+        let dir = TestDir::new();
+        let well = "Row\tColumn\tCount\n1\t1\t2\r\n\n\t\t\n1\t2\t3\n\n";
+        dir.write("p/well.txt", Datafile::new("P1", None, well));
+        let nuclei = "Row\tColumn\tArea\n1\t1\t5\n";
+        dir.write("p/nuclei.txt", Datafile::new("P1", Some("Nuclei"), nuclei));
+        let metadata = dir.metadata();
+        let opts = CombineOptions::default();
+
+        let plan = plan_combine(&metadata, &opts).unwrap();
+        let mut out = Vec::new();
+        let report = combine_files_with(&mut out, &metadata, &opts).unwrap();
+        let out = utf8(out);
+        let rows = cells(&out);
+
+        let header = plan.header.iter().map(|h| h.as_ref()).collect::<Vec<_>>();
+        assert_eq!(header, rows[0]);
+        assert_eq!(plan.rows(), rows.len() - 1);
+        // a line of tabs is a row of empty cells, a blank line is no row at all
+        let counts = plan.files.iter().map(|f| f.rows).collect::<Vec<_>>();
+        assert_eq!(counts, [1, 3]);
+        for f in &plan.files {
+            assert_eq!(report.rows[&f.path], f.rows);
+        }
+
+        let sources = plan
+            .columns
+            .iter()
+            .map(|c| (c.name.as_ref(), c.files.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                ("Row", vec![0, 1]),
+                ("Column", vec![0, 1]),
+                ("Area", vec![0]),
+                ("Count", vec![1]),
+            ]
+        );
+        assert_eq!(plan.files[1].missing, [Arc::from("Area")]);
+    }
+
+    #[test]
+    fn only_the_stacked_layout() {
+        let opts = CombineOptions {
+            layout: Layout::Wide,
+            ..CombineOptions::default()
+        };
+        assert!(plan_combine(&[], &opts).is_err());
+    }
+}
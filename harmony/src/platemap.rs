@@ -0,0 +1,395 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    info::HarmonyMetadata,
+    table::Table,
+    well::{collect_plate_wells, PlateFormat, Well, WellStyle},
+};
+
+/// Header names accepted for the plate column of a plate map
+const PLATE_HDRS: &[&str] = &["plate name", "plate"];
+/// Header names accepted for the well column of a plate map
+const WELL_HDRS: &[&str] = &["well", "well name", "well id"];
+
+/// Well annotations (compound, concentration, treatment, ...) read from CSV plate maps.
+///
+/// Each file needs a `Well` column and may have a `Plate Name` (or `Plate`) column;
+/// every other column is an annotation. Files without a plate column are global maps
+/// that apply to every plate without its own annotation for a well.
+#[derive(Debug, Clone, Default)]
+pub struct PlateMap {
+    columns: Vec<Arc<str>>,
+    plates: HashMap<String, HashMap<Well, Vec<String>>>,
+    global: HashMap<Well, Vec<String>>,
+}
+
+impl PlateMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read and merge plate maps from several files
+    pub fn from_files<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> io::Result<Self> {
+        let mut map = Self::new();
+        for p in paths {
+            map.read_file(p)?;
+        }
+        Ok(map)
+    }
+
+    /// Add the annotations from a CSV plate map.
+    ///
+    /// Annotation columns are merged by name with those already read;
+    /// a later file overwrites earlier values for the same plate and well.
+    pub fn read_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+
+        let hdrs = rdr.headers()?.clone();
+        let find = |names: &[&str]| {
+            hdrs.iter()
+                .position(|h| names.iter().any(|n| h.trim().eq_ignore_ascii_case(n)))
+        };
+        let plate_idx = find(PLATE_HDRS);
+        let well_idx = find(WELL_HDRS).ok_or_else(|| {
+            invalid_data(format!("plate map {} has no Well column", path.display()))
+        })?;
+        // map each annotation column of this file onto the merged columns
+        let ann = hdrs
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != plate_idx && *i != well_idx)
+            .map(|(i, h)| (i, self.column_or_insert(h.trim())))
+            .collect::<Vec<_>>();
+
+        for (line, rec) in rdr.records().enumerate() {
+            let rec = rec?;
+            let well_id = rec.get(well_idx).unwrap_or_default();
+            if well_id.trim().is_empty() {
+                continue;
+            }
+            let well = well_id.parse::<Well>().map_err(|e| {
+                // +2 for the header and 1-based lines
+                invalid_data(format!("{}:{}: {}", path.display(), line + 2, e))
+            })?;
+            let plate = plate_idx
+                .and_then(|i| rec.get(i))
+                .map(str::trim)
+                .filter(|p| !p.is_empty());
+
+            let n = self.columns.len();
+            let layout = match plate {
+                Some(p) => self.plates.entry(p.to_string()).or_default(),
+                None => &mut self.global,
+            };
+            let values = layout.entry(well).or_default();
+            values.resize(n, String::new());
+            for &(from, to) in &ann {
+                if let Some(v) = rec.get(from) {
+                    values[to] = v.trim().to_string();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn column_or_insert(&mut self, name: &str) -> usize {
+        match self.columns.iter().position(|c| c.as_ref() == name) {
+            Some(i) => i,
+            None => {
+                self.columns.push(Arc::from(name));
+                self.columns.len() - 1
+            }
+        }
+    }
+
+    /// Names of the annotation columns
+    pub fn columns(&self) -> &[Arc<str>] {
+        &self.columns
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.as_ref() == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plates.is_empty() && self.global.is_empty()
+    }
+
+    /// Does the map have a layout for this plate, either its own or the global one
+    pub fn has_plate(&self, plate: &str) -> bool {
+        self.plates.contains_key(plate) || !self.global.is_empty()
+    }
+
+    /// Annotations for a well, using the plate's own layout before the global one.
+    ///
+    /// The returned values line up with [`PlateMap::columns`]; columns that came from a
+    /// file read later than this well's file are missing from the end.
+    pub fn get(&self, plate: &str, well: Well) -> Option<&[String]> {
+        self.plates
+            .get(plate)
+            .and_then(|layout| layout.get(&well))
+            .or_else(|| self.global.get(&well))
+            .map(Vec::as_slice)
+    }
+
+    /// Annotation value of a single column for a well
+    pub fn value(&self, plate: &str, well: Well, column: usize) -> Option<&str> {
+        self.get(plate, well)
+            .and_then(|v| v.get(column))
+            .map(String::as_str)
+    }
+}
+
+/// The plate map column that marks control wells, and the values it uses for them
+#[derive(Debug, Clone)]
+pub struct Controls {
+    pub column: String,
+    /// value of negative control (e.g. vehicle) wells, compared without case
+    pub negative: String,
+    /// value of positive control wells, compared without case
+    pub positive: String,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            column: "Control".into(),
+            negative: "neg".into(),
+            positive: "pos".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ControlKind {
+    Negative,
+    Positive,
+}
+
+impl Controls {
+    /// Kind of control of every table row, looked up in the plate map by plate and well
+    pub(crate) fn classify_rows(
+        &self,
+        table: &Table,
+        map: &PlateMap,
+    ) -> io::Result<Vec<Option<ControlKind>>> {
+        let column = map.column_index(&self.column).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("plate map has no <{}> column marking controls", self.column),
+            )
+        })?;
+        let locator = table.locator()?;
+        let kinds = (0..table.rows.len())
+            .map(|row| {
+                let plate = locator.plate(table, row);
+                locator
+                    .well(table, row)
+                    .and_then(|w| map.value(plate, w, column))
+                    .and_then(|v| self.kind(v))
+            })
+            .collect();
+        Ok(kinds)
+    }
+
+    /// Kind of control a plate map value marks, if any
+    pub fn kind(&self, value: &str) -> Option<ControlKind> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case(&self.negative) {
+            Some(ControlKind::Negative)
+        } else if value.eq_ignore_ascii_case(&self.positive) {
+            Some(ControlKind::Positive)
+        } else {
+            None
+        }
+    }
+}
+
+/// Plates and wells that the plate map had no annotations for
+#[derive(Debug, Clone, Default)]
+pub struct AnnotationReport {
+    /// plates with neither their own layout nor a global one
+    pub missing_plates: BTreeSet<String>,
+    /// wells without annotations on plates that do have a layout
+    pub missing_wells: BTreeMap<String, BTreeSet<Well>>,
+}
+
+impl AnnotationReport {
+    pub fn is_empty(&self) -> bool {
+        self.missing_plates.is_empty() && self.missing_wells.is_empty()
+    }
+
+    pub(crate) fn record(&mut self, map: &PlateMap, plate: &str, well: Well) {
+        if !map.has_plate(plate) {
+            if !self.missing_plates.contains(plate) {
+                self.missing_plates.insert(plate.to_string());
+            }
+        } else if let Some(wells) = self.missing_wells.get_mut(plate) {
+            wells.insert(well);
+        } else {
+            self.missing_wells
+                .insert(plate.to_string(), BTreeSet::from([well]));
+        }
+    }
+}
+
+/// Annotation columns of a plate map template when none are given
+pub const DEFAULT_TEMPLATE_COLUMNS: &[&str] =
+    &["Compound", "Concentration", "Treatment", "Control"];
+
+/// Write a blank CSV plate map with one row for every plate and well that has data.
+///
+/// The plate names are written exactly as harmony spells them, followed by the
+/// well ID and an empty cell for each annotation column, so the filled-in
+/// template can be read back with [`PlateMap::read_file`].
+pub fn write_plate_map_template(
+    out: impl io::Write,
+    metadata: &[HarmonyMetadata],
+    columns: &[impl AsRef<str>],
+) -> io::Result<()> {
+    let plates = collect_plate_wells(metadata)?;
+    let mut wtr = csv::Writer::from_writer(out);
+
+    wtr.write_record(
+        ["Plate Name", "Well"]
+            .into_iter()
+            .chain(columns.iter().map(AsRef::as_ref)),
+    )?;
+    for (plate, wells) in &plates {
+        let format = PlateFormat::fitting(wells);
+        for well in wells {
+            let id = well.label(WellStyle::Padded, format);
+            wtr.write_record(
+                [plate.as_str(), id.as_str()]
+                    .into_iter()
+                    .chain(columns.iter().map(|_| "")),
+            )?;
+        }
+    }
+    wtr.flush()
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        test_data::{cells, utf8, Datafile, TestDir},
+        write::{combine_files_with, CombineOptions},
+    };
+
+    fn well(id: &str) -> Well {
+        id.parse().unwrap()
+    }
+
+    #[test]
+    fn plate_layouts_before_the_global_one() {
+        let dir = TestDir::new();
+        let own = dir.root.join("own.csv");
+        fs::write(
+            &own,
+            "Plate,Well,Compound,Control\nP1,A1,DMSO,neg\nP1, A02 ,Drug,\n",
+        )
+        .unwrap();
+        let global = dir.root.join("global.csv");
+        fs::write(
+            &global,
+            "well id,Treatment,Compound\nA01,heat,Global\nB01,cold,Other\n",
+        )
+        .unwrap();
+
+        let map = PlateMap::from_files([&own, &global]).unwrap();
+        assert_eq!(
+            map.columns(),
+            ["Compound", "Control", "Treatment"].map(Arc::from)
+        );
+        // read before the global map added its column
+        assert_eq!(map.get("P1", well("A01")).unwrap(), ["DMSO", "neg"]);
+        assert_eq!(map.get("P1", well("B01")).unwrap(), ["Other", "", "cold"]);
+        assert_eq!(map.value("P2", well("A01"), 2), Some("heat"));
+        assert_eq!(map.value("P1", well("A02"), 0), Some("Drug"));
+        assert_eq!(map.get("P1", well("C01")), None);
+        assert!(map.has_plate("P9"));
+
+        let bad = dir.root.join("bad.csv");
+        fs::write(&bad, "Well,Compound\nA01,x\n12,y\n").unwrap();
+        let err = PlateMap::from_files([&bad]).unwrap_err().to_string();
+        assert!(
+            err.ends_with("bad.csv:3: <12> does not start with a row letter"),
+            "{err}"
+        );
+        fs::write(&bad, "Plate,Compound\nP1,x\n").unwrap();
+        assert!(PlateMap::from_files([&bad]).is_err());
+    }
+
+    #[test]
+    fn annotations_joined_by_plate_and_well() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tCount\n1\t1\t2\n1\t3\t4\n";
+        dir.write("p1/well.txt", Datafile::new("P1", None, data));
+        dir.write("p2/well.txt", Datafile::new("P2", None, data));
+        let path = dir.root.join("map.csv");
+        fs::write(&path, "Plate Name,Well,Compound\nP1,A01,DMSO\n").unwrap();
+
+        let opts = CombineOptions {
+            plate_map: Some(PlateMap::from_files([&path]).unwrap()),
+            ..CombineOptions::default()
+        };
+        let mut out = Vec::new();
+        let report = combine_files_with(&mut out, &dir.metadata(), &opts).unwrap();
+        let out = utf8(out);
+        let rows = cells(&out);
+        let compound = rows[0].iter().position(|&h| h == "Compound").unwrap();
+        let count = rows[0].iter().position(|&h| h == "Count").unwrap();
+        assert!(compound < count);
+        let joined = rows[1..]
+            .iter()
+            .map(|r| (r[0], r[compound]))
+            .collect::<Vec<_>>();
+        assert_eq!(joined, [("P1", "DMSO"), ("P1", ""), ("P2", ""), ("P2", "")]);
+
+        let ann = &report.annotations;
+        assert_eq!(ann.missing_plates, BTreeSet::from(["P2".to_string()]));
+        let missing = BTreeMap::from([("P1".to_string(), BTreeSet::from([well("A03")]))]);
+        assert_eq!(ann.missing_wells, missing);
+    }
+
+    #[test]
+    fn template_of_the_wells_with_data() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tCount\n2\t1\t2\n1\t12\t4\n1\t12\t5\n";
+        dir.write("p1/well.txt", Datafile::new("P 1", None, data));
+        dir.write(
+            "p2/well.txt",
+            Datafile::new("P2", None, "Row\tColumn\tCount\n9\t1\t1\n"),
+        );
+
+        let mut out = Vec::new();
+        write_plate_map_template(&mut out, &dir.metadata(), &["Compound", "Dose"]).unwrap();
+        assert_eq!(
+            utf8(out),
+            "Plate Name,Well,Compound,Dose\n\
+             P 1,A12,,\n\
+             P 1,B01,,\n\
+             P2,I01,,\n"
+        );
+
+        // a filled-in template reads back as a plate map
+        let path = dir.root.join("map.csv");
+        fs::write(&path, "Plate Name,Well,Compound,Dose\nP 1,A12,DMSO,0\n").unwrap();
+        let map = PlateMap::from_files([&path]).unwrap();
+        assert_eq!(map.get("P 1", well("A12")).unwrap(), ["DMSO", "0"]);
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, Write},
+    sync::Arc,
+};
+
+use crate::{
+    platemap::{ControlKind, Controls, PlateMap},
+    stats::{self, parse_value},
+    table::{missing_column, Table, PLATE_GROUP_HDRS, PLATE_HDR},
+    write::write_interspersed,
+};
+
+/// Assay quality of each plate from its control wells
+#[derive(Debug, Clone, Default)]
+pub struct QcOptions {
+    /// columns to check
+    pub features: Vec<String>,
+    pub controls: Controls,
+    pub thresholds: QcThresholds,
+}
+
+/// Limits below which a plate is flagged; `None` turns a check off
+#[derive(Debug, Clone, Copy)]
+pub struct QcThresholds {
+    pub min_z_prime: Option<f64>,
+    /// compared against the size of the SSMD, whichever way the controls differ
+    pub min_ssmd: Option<f64>,
+    /// compared against the larger of S/B and B/S
+    pub min_signal_background: Option<f64>,
+    /// largest CV, in percent, allowed for either control
+    pub max_control_cv: Option<f64>,
+}
+
+impl Default for QcThresholds {
+    fn default() -> Self {
+        Self {
+            min_z_prime: Some(0.5),
+            min_ssmd: None,
+            min_signal_background: None,
+            max_control_cv: None,
+        }
+    }
+}
+
+/// Reason a plate was flagged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QcFlag {
+    /// fewer than two negative or positive control values
+    MissingControls,
+    ZPrime,
+    Ssmd,
+    SignalBackground,
+    ControlCv,
+}
+
+impl fmt::Display for QcFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingControls => write!(f, "controls"),
+            Self::ZPrime => write!(f, "Z'"),
+            Self::Ssmd => write!(f, "SSMD"),
+            Self::SignalBackground => write!(f, "S/B"),
+            Self::ControlCv => write!(f, "CV"),
+        }
+    }
+}
+
+/// Control statistics of one feature on one plate
+#[derive(Debug, Clone)]
+pub struct PlateQc {
+    /// values of [`QcReport::group_columns`] for this plate
+    pub group: Vec<String>,
+    pub plate: String,
+    pub feature: String,
+    pub n_negative: usize,
+    pub n_positive: usize,
+    pub negative_mean: f64,
+    pub negative_sd: f64,
+    pub positive_mean: f64,
+    pub positive_sd: f64,
+    /// 1 − 3 (σp + σn) / |μp − μn|
+    pub z_prime: f64,
+    /// (μp − μn) / √(σp² + σn²)
+    pub ssmd: f64,
+    /// μp / μn
+    pub signal_background: f64,
+    /// coefficients of variation in percent
+    pub negative_cv: f64,
+    pub positive_cv: f64,
+    pub flags: Vec<QcFlag>,
+}
+
+impl PlateQc {
+    pub fn passed(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    fn check(&mut self, t: &QcThresholds) {
+        // statistics that couldn't be computed fail every check they're part of
+        let below = |v: f64, min: Option<f64>| min.is_some_and(|min| v.is_nan() || v < min);
+        let above = |v: f64, max: Option<f64>| max.is_some_and(|max| v.is_nan() || v > max);
+
+        if self.n_negative < 2 || self.n_positive < 2 {
+            self.flags.push(QcFlag::MissingControls);
+        }
+        if below(self.z_prime, t.min_z_prime) {
+            self.flags.push(QcFlag::ZPrime);
+        }
+        if below(self.ssmd.abs(), t.min_ssmd) {
+            self.flags.push(QcFlag::Ssmd);
+        }
+        let window = self.signal_background.max(self.signal_background.recip());
+        if below(window, t.min_signal_background) {
+            self.flags.push(QcFlag::SignalBackground);
+        }
+        if above(self.negative_cv, t.max_control_cv) || above(self.positive_cv, t.max_control_cv) {
+            self.flags.push(QcFlag::ControlCv);
+        }
+    }
+}
+
+/// QC of every plate and feature, in plate order
+#[derive(Debug, Clone, Default)]
+pub struct QcReport {
+    /// the plate-identifying columns (plate name, measurement, ...) the table had
+    pub group_columns: Vec<Arc<str>>,
+    pub plates: Vec<PlateQc>,
+}
+
+const QC_HDRS: &[&str] = &[
+    "Feature",
+    "Negative Count",
+    "Positive Count",
+    "Negative Mean",
+    "Negative SD",
+    "Positive Mean",
+    "Positive SD",
+    "Z'",
+    "SSMD",
+    "S/B",
+    "Negative CV",
+    "Positive CV",
+    "Pass",
+    "Flags",
+];
+
+impl QcReport {
+    /// Plate groups flagged for any feature, as their values of
+    /// [`group_columns`](Self::group_columns), so one failed measurement or population
+    /// doesn't take the rest of its plate with it
+    pub fn failed_groups(&self) -> BTreeSet<Vec<&str>> {
+        self.plates
+            .iter()
+            .filter(|p| !p.passed())
+            .map(|p| p.group.iter().map(String::as_str).collect())
+            .collect()
+    }
+
+    /// Write the report as a tab separated table, one row per plate and feature
+    pub fn write_tsv(&self, mut wtr: impl Write) -> io::Result<()> {
+        write_interspersed(&mut wtr, &self.group_columns, "\t")?;
+        for h in QC_HDRS {
+            write!(wtr, "\t{h}")?;
+        }
+        writeln!(wtr)?;
+
+        for p in &self.plates {
+            write_interspersed(&mut wtr, &p.group, "\t")?;
+            write!(wtr, "\t{}\t{}\t{}", p.feature, p.n_negative, p.n_positive)?;
+            let values = [
+                p.negative_mean,
+                p.negative_sd,
+                p.positive_mean,
+                p.positive_sd,
+                p.z_prime,
+                p.ssmd,
+                p.signal_background,
+                p.negative_cv,
+                p.positive_cv,
+            ];
+            for v in values {
+                if v.is_finite() {
+                    write!(wtr, "\t{v}")?;
+                } else {
+                    write!(wtr, "\t")?;
+                }
+            }
+            let flags = p.flags.iter().map(ToString::to_string).collect::<Vec<_>>();
+            writeln!(
+                wtr,
+                "\t{}\t{}",
+                if p.passed() { "yes" } else { "no" },
+                flags.join(",")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compute Z'-factor, SSMD, signal-to-background and control CVs for each plate and feature.
+///
+/// Plates are split the same way as for [`normalise`](crate::normalise), with the
+/// negative and positive control wells found through the plate map.
+pub fn plate_qc(table: &Table, plate_map: &PlateMap, opts: &QcOptions) -> io::Result<QcReport> {
+    let kinds = opts.controls.classify_rows(table, plate_map)?;
+    let plate_col = table
+        .column(PLATE_HDR)
+        .ok_or_else(|| missing_column(PLATE_HDR))?;
+    let group_columns = PLATE_GROUP_HDRS
+        .iter()
+        .filter(|h| table.column(h).is_some())
+        .map(|&h| Arc::from(h))
+        .collect();
+    let groups = table.group_by(PLATE_GROUP_HDRS);
+
+    let mut plates = Vec::new();
+    for (key, rows) in &groups {
+        for feature in &opts.features {
+            let col = table
+                .column(feature)
+                .ok_or_else(|| missing_column(feature))?;
+            if !table.has_feature(col, rows.iter().copied()) {
+                continue;
+            }
+            let values = |kind| {
+                rows.iter()
+                    .filter(|&&r| kinds[r] == Some(kind))
+                    .filter_map(|&r| parse_value(table.cell(r, col)))
+                    .collect::<Vec<_>>()
+            };
+            let neg = values(ControlKind::Negative);
+            let pos = values(ControlKind::Positive);
+
+            let (mn, sn) = (stats::mean(&neg), stats::std_dev(&neg));
+            let (mp, sp) = (stats::mean(&pos), stats::std_dev(&pos));
+            let mut qc = PlateQc {
+                group: key.iter().map(|s| s.to_string()).collect(),
+                plate: table.cell(rows[0], plate_col).to_string(),
+                feature: feature.clone(),
+                n_negative: neg.len(),
+                n_positive: pos.len(),
+                negative_mean: mn,
+                negative_sd: sn,
+                positive_mean: mp,
+                positive_sd: sp,
+                z_prime: 1.0 - 3.0 * (sp + sn) / (mp - mn).abs(),
+                ssmd: (mp - mn) / (sp.powi(2) + sn.powi(2)).sqrt(),
+                signal_background: mp / mn,
+                negative_cv: 100.0 * sn / mn.abs(),
+                positive_cv: 100.0 * sp / mp.abs(),
+                flags: Vec::new(),
+            };
+            qc.check(&opts.thresholds);
+            plates.push(qc);
+        }
+    }
+
+    Ok(QcReport {
+        group_columns,
+        plates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{table, TestDir};
+
+    const DATA: &str = "Plate Name\tMeasurement\tPopulation\tRow\tColumn\tCount\tArea\n\
+                        P1\t1\tWell\t1\t1\t10\t\n\
+                        P1\t1\tWell\t1\t2\t12\t\n\
+                        P1\t1\tWell\t1\t3\t50\t\n\
+                        P1\t1\tWell\t1\t4\t52\t\n\
+                        P1\t1\tNuclei\t1\t1\t\t3\n\
+                        P1\t2\tWell\t1\t1\t10\t\n\
+                        P1\t2\tWell\t1\t2\t20\t\n\
+                        P1\t2\tWell\t1\t3\t30\t\n\
+                        P1\t2\tWell\t1\t4\t40\t\n";
+
+    fn qc(thresholds: QcThresholds) -> (Table, QcReport) {
+        let dir = TestDir::new();
+        let map = dir.plate_map("Well,Control\nA01,neg\nA02,neg\nA03,pos\nA04,pos\n");
+        let table = table(DATA);
+        let opts = QcOptions {
+            features: vec!["Count".into()],
+            thresholds,
+            ..QcOptions::default()
+        };
+        let report = plate_qc(&table, &map, &opts).unwrap();
+        (table, report)
+    }
+
+    #[test]
+    fn z_prime_of_each_measurement() {
+        let (mut table, report) = qc(QcThresholds::default());
+        assert_eq!(
+            report.group_columns,
+            ["Plate Name", "Measurement", "Population"].map(Arc::from)
+        );
+        // the nuclei have no counts, so they get no row rather than a failed one
+        let groups = report.plates.iter().map(|p| &p.group).collect::<Vec<_>>();
+        assert_eq!(groups, [&["P1", "1", "Well"], &["P1", "2", "Well"]]);
+
+        let good = &report.plates[0];
+        assert_eq!((good.n_negative, good.n_positive), (2, 2));
+        assert_eq!((good.negative_mean, good.positive_mean), (11.0, 51.0));
+        let sd = 2f64.sqrt();
+        assert!((good.z_prime - (1.0 - 6.0 * sd / 40.0)).abs() < 1e-12);
+        assert!(good.passed());
+        let bad = &report.plates[1];
+        assert_eq!(bad.flags, [QcFlag::ZPrime]);
+
+        // only the failed measurement is dropped, with its own population's rows
+        let failed = report.failed_groups();
+        assert_eq!(failed, BTreeSet::from([vec!["P1", "2", "Well"]]));
+        table.retain_plates(|key| !failed.contains(key));
+        let kept = table.rows.iter().map(|r| r[1].as_str()).collect::<Vec<_>>();
+        assert_eq!(kept, ["1", "1", "1", "1", "1"]);
+
+        let mut out = Vec::new();
+        report.write_tsv(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let last = out.lines().last().unwrap();
+        assert!(last.starts_with("P1\t2\tWell\tCount\t2\t2\t15\t"));
+        assert!(last.ends_with("\tno\tZ'"));
+    }
+
+    #[test]
+    fn checks_turned_off() {
+        let none = QcThresholds {
+            min_z_prime: None,
+            ..QcThresholds::default()
+        };
+        let (_, report) = qc(none);
+        assert!(report.plates.iter().all(PlateQc::passed));
+        assert!(report.failed_groups().is_empty());
+
+        let strict = QcThresholds {
+            min_z_prime: None,
+            min_signal_background: Some(4.0),
+            max_control_cv: Some(15.0),
+            ..QcThresholds::default()
+        };
+        let (_, report) = qc(strict);
+        assert!(report.plates[0].passed());
+        assert_eq!(
+            report.plates[1].flags,
+            [QcFlag::SignalBackground, QcFlag::ControlCv]
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    heatmap::{write_heatmap, ColourRange, HeatmapOptions, ImageFormat},
+    info::{HarmonyMetadata, ScanReport},
+    matrix::PlateMatrix,
+    utils::escape_xml,
+    write::{population_label, CombineReport},
+};
+
+/// Everything that goes into a run report
+#[derive(Debug, Clone, Copy)]
+pub struct RunReport<'a> {
+    pub scan: &'a ScanReport,
+    pub combine: &'a CombineReport,
+    /// plate grids to draw as heatmaps, see [`plate_matrices`](crate::plate_matrices)
+    pub heatmaps: &'a [PlateMatrix],
+    pub heatmap_range: ColourRange,
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#202020}\
+table{border-collapse:collapse;margin-bottom:1em}\
+th,td{border:1px solid #ccc;padding:0.2em 0.6em;text-align:left}\
+th{background:#f0f0f0}td.n{text-align:right}\
+.warn{color:#a04000}figure{display:inline-block;margin:0.5em}";
+
+impl RunReport<'_> {
+    /// Write a single HTML page, with styles and heatmaps inline so it can be shared
+    /// as one file: the files included and skipped, the plates and their row counts,
+    /// columns that differ between files of a population, warnings from combining,
+    /// and the heatmaps.
+    pub fn write_html(&self, mut wtr: impl Write) -> io::Result<()> {
+        let files = &self.scan.files;
+        let rows = files
+            .iter()
+            .map(|m| self.combine.rows.get(&m.path).copied().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        writeln!(wtr, "<!DOCTYPE html>")?;
+        writeln!(wtr, r#"<html lang="en"><head><meta charset="utf-8">"#)?;
+        writeln!(wtr, "<title>Harmony combine report</title>")?;
+        writeln!(wtr, "<style>{STYLE}</style></head><body>")?;
+        writeln!(wtr, "<h1>Harmony combine report</h1>")?;
+        writeln!(
+            wtr,
+            "<p>{} files from <code>{}</code>, {} skipped; {} rows on {} plates.</p>",
+            files.len(),
+            escape_xml(&self.scan.root.display().to_string()),
+            self.scan.skipped.len(),
+            rows.iter().sum::<usize>(),
+            files
+                .iter()
+                .map(|m| &m.plate_name)
+                .collect::<BTreeSet<_>>()
+                .len(),
+        )?;
+
+        self.write_plates(&mut wtr, &rows)?;
+        self.write_files(&mut wtr, &rows)?;
+        self.write_schema(&mut wtr)?;
+        self.write_warnings(&mut wtr)?;
+        self.write_heatmaps(&mut wtr)?;
+
+        writeln!(wtr, "</body></html>")
+    }
+
+    fn write_plates(&self, wtr: &mut impl Write, rows: &[usize]) -> io::Result<()> {
+        #[derive(Default)]
+        struct Plate<'a> {
+            measurements: BTreeSet<u32>,
+            evaluations: BTreeSet<u32>,
+            populations: BTreeSet<&'a str>,
+            files: usize,
+            rows: usize,
+        }
+        let mut plates: BTreeMap<&str, Plate> = BTreeMap::new();
+        for (m, n) in self.scan.files.iter().zip(rows) {
+            let p = plates.entry(&m.plate_name).or_default();
+            p.measurements.insert(m.measurement);
+            p.evaluations.insert(m.evaluation);
+            p.populations.insert(population_label(m));
+            p.files += 1;
+            p.rows += n;
+        }
+
+        writeln!(wtr, "<h2>Plates</h2>")?;
+        writeln!(wtr, "<table><tr><th>Plate</th><th>Measurements</th><th>Evaluations</th><th>Populations</th><th>Files</th><th>Rows</th></tr>")?;
+        for (name, p) in &plates {
+            writeln!(
+                wtr,
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class="n">{}</td><td class="n">{}</td></tr>"#,
+                escape_xml(name),
+                join(&p.measurements),
+                join(&p.evaluations),
+                escape_xml(&join(&p.populations)),
+                p.files,
+                p.rows
+            )?;
+        }
+        writeln!(wtr, "</table>")
+    }
+
+    fn write_files(&self, wtr: &mut impl Write, rows: &[usize]) -> io::Result<()> {
+        let mut order = (0..self.scan.files.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| &self.scan.files[i].path);
+
+        writeln!(wtr, "<h2>Files</h2>")?;
+        writeln!(wtr, "<table><tr><th>File</th><th>Plate</th><th>Measurement</th><th>Evaluation</th><th>Population</th><th>Database</th><th>Columns</th><th>Rows</th></tr>")?;
+        for i in order {
+            let m = &self.scan.files[i];
+            writeln!(
+                wtr,
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class="n">{}</td><td class="n">{}</td></tr>"#,
+                escape_xml(&self.relative(&m.path)),
+                escape_xml(&m.plate_name),
+                m.measurement,
+                m.evaluation,
+                escape_xml(population_label(m)),
+                escape_xml(&m.db_name),
+                m.headers.len(),
+                rows[i]
+            )?;
+        }
+        writeln!(wtr, "</table>")?;
+
+        if !self.scan.skipped.is_empty() {
+            writeln!(wtr, "<h3>Skipped</h3>")?;
+            writeln!(
+                wtr,
+                "<p>These text files could not be read as harmony exports:</p><ul>"
+            )?;
+            for p in &self.scan.skipped {
+                writeln!(wtr, "<li>{}</li>", escape_xml(&self.relative(p)))?;
+            }
+            writeln!(wtr, "</ul>")?;
+        }
+        Ok(())
+    }
+
+    /// Columns that only some files of a population have
+    fn write_schema(&self, wtr: &mut impl Write) -> io::Result<()> {
+        let mut populations: BTreeMap<&str, Vec<&HarmonyMetadata>> = BTreeMap::new();
+        for m in &self.scan.files {
+            populations.entry(population_label(m)).or_default().push(m);
+        }
+
+        writeln!(wtr, "<h2>Columns</h2>")?;
+        let mut any = false;
+        for (pop, files) in &populations {
+            let columns = files
+                .iter()
+                .flat_map(|m| m.headers.iter())
+                .collect::<BTreeSet<&Arc<str>>>();
+            let partial = columns
+                .into_iter()
+                .filter_map(|c| {
+                    let lacking = files
+                        .iter()
+                        .filter(|m| !m.headers.contains(c))
+                        .collect::<Vec<_>>();
+                    (!lacking.is_empty()).then_some((c, lacking))
+                })
+                .collect::<Vec<_>>();
+            if partial.is_empty() {
+                continue;
+            }
+            if !any {
+                writeln!(
+                    wtr,
+                    "<table><tr><th>Population</th><th>Column</th><th>Files without it</th></tr>"
+                )?;
+                any = true;
+            }
+            for (column, lacking) in partial {
+                let listed = lacking
+                    .iter()
+                    .map(|m| escape_xml(&self.relative(&m.path)))
+                    .collect::<Vec<_>>();
+                writeln!(
+                    wtr,
+                    "<tr><td>{}</td><td>{}</td><td>{} of {}: {}</td></tr>",
+                    escape_xml(pop),
+                    escape_xml(column),
+                    lacking.len(),
+                    files.len(),
+                    listed.join("<br>")
+                )?;
+            }
+        }
+        if any {
+            writeln!(wtr, "</table>")
+        } else {
+            writeln!(
+                wtr,
+                "<p>All files of each population have the same columns.</p>"
+            )
+        }
+    }
+
+    fn write_warnings(&self, wtr: &mut impl Write) -> io::Result<()> {
+        let warnings = self.combine.warnings();
+        if warnings.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(wtr, "<h2>Warnings</h2>")?;
+        writeln!(wtr, r#"<ul class="warn">"#)?;
+        for w in warnings {
+            writeln!(wtr, "<li>{}</li>", escape_xml(&w))?;
+        }
+        writeln!(wtr, "</ul>")
+    }
+
+    fn write_heatmaps(&self, wtr: &mut impl Write) -> io::Result<()> {
+        if self.heatmaps.is_empty() {
+            return Ok(());
+        }
+        let opts = HeatmapOptions {
+            format: ImageFormat::Svg,
+            range: self.heatmap_range,
+        };
+        let mut features: BTreeMap<&str, Vec<&PlateMatrix>> = BTreeMap::new();
+        for m in self.heatmaps {
+            features.entry(&m.feature).or_default().push(m);
+        }
+
+        writeln!(wtr, "<h2>Heatmaps</h2>")?;
+        for (feature, matrices) in features {
+            writeln!(wtr, "<h3>{}</h3>", escape_xml(feature))?;
+            for m in matrices {
+                writeln!(wtr, "<figure>")?;
+                write_heatmap(&mut *wtr, m, &opts)?;
+                writeln!(wtr, "</figure>")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Path of a file below the scanned directory
+    fn relative(&self, p: &Path) -> String {
+        p.strip_prefix(&self.scan.root)
+            .unwrap_or(p)
+            .display()
+            .to_string()
+    }
+}
+
+fn join<T: ToString>(values: &BTreeSet<T>) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        info::scan_harmony_datafiles,
+        test_data::{utf8, Datafile, TestDir},
+        write::{combine_files_with, CombineOptions, Layout},
+    };
+
+    fn html(dir: &TestDir, opts: &CombineOptions) -> String {
+        let scan = scan_harmony_datafiles(&dir.root);
+        let combine = combine_files_with(io::sink(), &scan.files, opts).unwrap();
+        let report = RunReport {
+            scan: &scan,
+            combine: &combine,
+            heatmaps: &[],
+            heatmap_range: ColourRange::default(),
+        };
+        let mut out = Vec::new();
+        report.write_html(&mut out).unwrap();
+        utf8(out)
+    }
+
+    #[test]
+    fn rows_written_rather_than_read() {
+        let dir = TestDir::new();
+        let well = "Row\tColumn\tCount\n1\t1\t2\n1\t2\t3\n";
+        dir.write("p/well.txt", Datafile::new("P1", None, well));
+        let nuclei = "Row\tColumn\tArea\n1\t1\t5\n1\t1\t6\n1\t2\t7\n";
+        dir.write("p/nuclei.txt", Datafile::new("P1", Some("Nuclei"), nuclei));
+
+        let stacked = html(&dir, &CombineOptions::default());
+        assert!(stacked.contains("<p>2 files from"));
+        assert!(stacked.contains("5 rows on 1 plates"));
+
+        // the wide layout keeps one row per well of each population
+        let wide = CombineOptions {
+            layout: Layout::Wide,
+            ..CombineOptions::default()
+        };
+        let wide = html(&dir, &wide);
+        assert!(wide.contains("4 rows on 1 plates"));
+        assert!(wide.contains(
+            r#"<tr><td>p/nuclei.txt</td><td>P1</td><td>1</td><td>1</td><td>Nuclei</td><td>HarmonyDB</td><td class="n">3</td><td class="n">2</td></tr>"#
+        ));
+        assert!(wide.contains("dropped 1 row of population &lt;Nuclei&gt;"));
+    }
+
+    #[test]
+    fn names_from_exports_are_escaped() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tArea\n1\t1\t5\n";
+        let file = Datafile::new("<b>P&1</b>", Some("Nuc\"lei<"), data);
+        dir.write("R&D <2>/nuclei.txt", file);
+
+        // a pattern the plate doesn't match puts its name in the warnings too
+        let opts = CombineOptions {
+            plate_pattern: Some("^(?P<lab>X)$".parse().unwrap()),
+            ..CombineOptions::default()
+        };
+        let html = html(&dir, &opts);
+        assert!(!html.contains("<b>"));
+        assert!(!html.contains("R&D"));
+        assert!(!html.contains("lei<"));
+        assert!(html.contains("<td>&lt;b&gt;P&amp;1&lt;/b&gt;</td>"));
+        assert!(html.contains("<td>Nuc&quot;lei&lt;</td>"));
+        assert!(html.contains("<td>R&amp;D &lt;2&gt;/nuclei.txt</td>"));
+        assert!(html.contains("<li>plate name &lt;&lt;b&gt;P&amp;1&lt;/b&gt;&gt; does not match"));
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    ops::Range,
+    sync::Arc,
+};
+
+use crate::{
+    info::HarmonyMetadata,
+    utils::{column_index, data_line, has_leading_zero, open_data},
+    write::{combined_header, CombineOptions, CombineReport, MetaColumns},
+};
+
+/// Rows of harmony datafiles, read a line at a time and laid out in one header.
+///
+/// Rows borrow the reader's line buffer, so they are read with
+/// [`next_row`](Rows::next_row) rather than as an [`Iterator`]:
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let metadata = harmony::collect_harmony_datafiles("exports");
+/// let mut rows = harmony::Rows::new(&metadata);
+/// while let Some(row) = rows.next_row() {
+///     let row = row?;
+///     println!("{} {:?}", row.metadata.plate_name, row.get_by_name("Row"));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Rows<'a> {
+    files: Vec<&'a HarmonyMetadata>,
+    header: Vec<Arc<str>>,
+    /// file being read, and its reader once opened
+    current: usize,
+    rdr: Option<BufReader<File>>,
+    /// column of the current file that each header column comes from
+    sources: Vec<Option<usize>>,
+    line: usize,
+    buf: String,
+    fields: Vec<Range<usize>>,
+    spans: Vec<Option<Range<usize>>>,
+    /// columns derived from the plate name and path, then the well ID and plate map
+    /// columns, for rows read with [`Rows::with_options`]
+    meta: Option<MetaColumns<'a>>,
+    meta_header: Vec<Arc<str>>,
+    /// their values for the current file, then the current row
+    meta_values: Vec<String>,
+    derived: usize,
+    report: CombineReport,
+}
+
+impl<'a> Rows<'a> {
+    /// Rows of every file in the combined header written by
+    /// [`combine_files_with`](crate::combine_files_with): the first file's columns, then
+    /// the columns only other files have
+    pub fn new(metadata: &'a [HarmonyMetadata]) -> Self {
+        Self::with_header(metadata, combined_header(metadata))
+    }
+
+    /// Rows of `files` laid out in `header`. File columns not in the header are dropped.
+    pub fn with_header(
+        files: impl IntoIterator<Item = &'a HarmonyMetadata>,
+        header: Vec<Arc<str>>,
+    ) -> Self {
+        Self {
+            files: files.into_iter().collect(),
+            spans: vec![None; header.len()],
+            header,
+            current: 0,
+            rdr: None,
+            sources: Vec::new(),
+            line: 0,
+            buf: String::with_capacity(0x400),
+            fields: Vec::new(),
+            meta: None,
+            meta_header: Vec::new(),
+            meta_values: Vec::new(),
+            derived: 0,
+            report: CombineReport::default(),
+        }
+    }
+
+    /// Rows of every file in the combined header, with the columns `opts` adds to the
+    /// combined output as well: those from the plate name pattern and path template,
+    /// the well ID and the plate map annotations. They are found with
+    /// [`Row::get_by_name`] and [`Row::deserialize`].
+    ///
+    /// The layout and aggregation options are ignored.
+    pub fn with_options(
+        metadata: &'a [HarmonyMetadata],
+        opts: &'a CombineOptions,
+    ) -> io::Result<Self> {
+        let mut rows = Self::new(metadata);
+        let meta = MetaColumns::new(metadata, opts, &rows.header)?;
+        let derived = meta.derived_header();
+        rows.derived = derived.len();
+        rows.meta_header = derived
+            .into_iter()
+            .chain(meta.well_header())
+            .map(Arc::from)
+            .collect();
+        rows.meta = Some(meta);
+        Ok(rows)
+    }
+
+    pub fn header(&self) -> &[Arc<str>] {
+        &self.header
+    }
+
+    /// Plates, paths and wells the [`with_options`](Self::with_options) columns
+    /// couldn't be filled in for, among the rows read so far
+    pub fn report(&self) -> &CombineReport {
+        &self.report
+    }
+
+    /// Read the next non-empty row, opening the next file when one runs out.
+    ///
+    /// Reading stops at the first error.
+    pub fn next_row(&mut self) -> Option<io::Result<Row<'_>>> {
+        match self.advance() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => {
+                self.rdr = None;
+                self.current = self.files.len();
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(Row {
+            metadata: self.files[self.current],
+            file: self.current,
+            header: &self.header,
+            line: self.line,
+            text: &self.buf,
+            spans: &self.spans,
+            meta_header: &self.meta_header,
+            meta_values: &self.meta_values,
+        }))
+    }
+
+    /// Load the next non-empty line into the buffer, or return false after the last file
+    fn advance(&mut self) -> io::Result<bool> {
+        loop {
+            let rdr = match &mut self.rdr {
+                Some(rdr) => rdr,
+                None => {
+                    let m = match self.files.get(self.current) {
+                        Some(m) => m,
+                        None => return Ok(false),
+                    };
+                    self.sources = self
+                        .header
+                        .iter()
+                        .map(|h| column_index(&m.headers, h))
+                        .collect();
+                    self.line = m.data_start as usize;
+                    if let Some(meta) = &self.meta {
+                        let derived = meta.derived_fields(m, &mut self.report);
+                        self.meta_values = derived.into_iter().map(str::to_string).collect();
+                    }
+                    self.rdr.insert(open_data(m)?)
+                }
+            };
+
+            self.buf.clear();
+            if rdr.read_line(&mut self.buf)? == 0 {
+                self.rdr = None;
+                self.current += 1;
+                continue;
+            }
+            self.line += 1;
+            let text = match data_line(&self.buf) {
+                Some(text) => text,
+                None => continue,
+            };
+
+            self.fields.clear();
+            let mut start = 0;
+            for field in text.split('\t') {
+                let end = start + field.len();
+                self.fields.push(start..end);
+                start = end + 1;
+            }
+            for (span, source) in self.spans.iter_mut().zip(&self.sources) {
+                *span = source.map(|k| self.fields.get(k).cloned().unwrap_or(0..0));
+            }
+            if let Some(meta) = &self.meta {
+                if self.meta_header.len() > self.derived {
+                    let cells = self
+                        .spans
+                        .iter()
+                        .map(|s| s.clone().map_or("", |s| &text[s]))
+                        .collect::<Vec<_>>();
+                    let plate = &self.files[self.current].plate_name;
+                    let well = meta.well_values(plate, meta.well(&cells), &mut self.report);
+                    self.meta_values.truncate(self.derived);
+                    self.meta_values.extend(well);
+                }
+            }
+            return Ok(true);
+        }
+    }
+}
+
+/// One row of a datafile, laid out in the header of the [`Rows`] it was read from
+#[derive(Debug, Clone, Copy)]
+pub struct Row<'r> {
+    /// the datafile the row came from
+    pub metadata: &'r HarmonyMetadata,
+    /// position of the datafile among the files being read
+    pub file: usize,
+    pub header: &'r [Arc<str>],
+    /// line of the datafile, counting from 1
+    pub line: usize,
+    text: &'r str,
+    spans: &'r [Option<Range<usize>>],
+    meta_header: &'r [Arc<str>],
+    meta_values: &'r [String],
+}
+
+impl<'r> Row<'r> {
+    /// Cell of a header column, or `None` when the datafile doesn't have that column
+    pub fn get(&self, i: usize) -> Option<&'r str> {
+        self.spans.get(i)?.clone().map(|span| &self.text[span])
+    }
+
+    /// Cell of a header column, or of a column added by [`Rows::with_options`]
+    pub fn get_by_name(&self, name: &str) -> Option<&'r str> {
+        match column_index(self.header, name) {
+            Some(i) => self.get(i),
+            None => column_index(self.meta_header, name).map(|i| self.meta_values[i].as_str()),
+        }
+    }
+
+    /// Columns added by [`Rows::with_options`] and their values for this row
+    pub fn meta_columns(&self) -> impl Iterator<Item = (&'r str, &'r str)> {
+        let values = self.meta_values.iter().map(String::as_str);
+        self.meta_header.iter().map(|h| h.as_ref()).zip(values)
+    }
+
+    /// Cell of a header column, empty when the datafile doesn't have that column
+    pub fn cell(&self, i: usize) -> &'r str {
+        self.get(i).unwrap_or_default()
+    }
+
+    /// Every cell of the row, in header order
+    pub fn cells(&self) -> impl Iterator<Item = &'r str> {
+        let row = *self;
+        (0..row.header.len()).map(move |i| row.cell(i))
+    }
+
+    /// Cell of a header column as a number where it is one
+    pub fn value(&self, i: usize) -> Value<'r> {
+        self.get(i).map_or(Value::Null, Value::parse)
+    }
+}
+
+/// A cell as the type its text holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value<'a> {
+    /// an empty cell, or a column the datafile doesn't have
+    Null,
+    Integer(i64),
+    /// any other number, including `NaN`
+    Real(f64),
+    /// text, and numbers with leading zeros such as `007`
+    Text(&'a str),
+}
+
+impl<'a> Value<'a> {
+    pub fn parse(s: &'a str) -> Self {
+        if s.is_empty() {
+            Self::Null
+        } else if has_leading_zero(s) {
+            Self::Text(s)
+        } else if let Ok(n) = s.parse() {
+            Self::Integer(n)
+        } else if let Ok(n) = s.parse() {
+            Self::Real(n)
+        } else {
+            Self::Text(s)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_data::{Datafile, TestDir},
+        write::WellOptions,
+    };
+
+    fn write_files(dir: &TestDir) {
+        let well = "Row\tColumn\tCount\tCode\n1\t1\t2\t007\r\n\n\t\t\t\n1\t2\tNaN\t12\n";
+        dir.write("p/well.txt", Datafile::new("P1", None, well));
+        let nuclei = "Row\tColumn\tArea\n1\t1\t5.5\n";
+        dir.write("p/nuclei.txt", Datafile::new("P1", Some("Nuclei"), nuclei));
+    }
+
+    #[test]
+    fn rows_in_the_combined_header() {
+        let dir = TestDir::new();
+        write_files(&dir);
+        let metadata = dir.metadata();
+        let mut rows = Rows::new(&metadata);
+        assert_eq!(
+            rows.header(),
+            ["Row", "Column", "Area", "Code", "Count"].map(Arc::from)
+        );
+
+        let row = rows.next_row().unwrap().unwrap();
+        assert_eq!((row.file, row.get(2), row.get(3)), (0, Some("5.5"), None));
+        assert_eq!(row.value(2), Value::Real(5.5));
+        assert_eq!(row.value(4), Value::Null);
+
+        let row = rows.next_row().unwrap().unwrap();
+        let first = row.line;
+        assert_eq!(row.metadata.population, None);
+        assert_eq!(row.cells().collect::<Vec<_>>(), ["1", "1", "", "007", "2"]);
+        assert_eq!(row.value(3), Value::Text("007"));
+        assert_eq!(row.value(4), Value::Integer(2));
+        assert_eq!(row.get_by_name("Code"), Some("007"));
+
+        // the blank line is skipped, but a line of tabs is a row of empty cells
+        let row = rows.next_row().unwrap().unwrap();
+        assert_eq!(row.line, first + 2);
+        assert_eq!(row.get(4), Some(""));
+        let row = rows.next_row().unwrap().unwrap();
+        assert_eq!(row.line, first + 3);
+        assert!(matches!(row.value(4), Value::Real(v) if v.is_nan()));
+        assert!(rows.next_row().is_none());
+    }
+
+    #[test]
+    fn columns_added_by_the_options() {
+        let dir = TestDir::new();
+        write_files(&dir);
+        let opts = CombineOptions {
+            well: Some(WellOptions::default()),
+            plate_map: Some(dir.plate_map("Well,Compound\nA01,DMSO\n")),
+            ..CombineOptions::default()
+        };
+        let metadata = dir.metadata();
+        let mut rows = Rows::with_options(&metadata, &opts).unwrap();
+        let mut wells = Vec::new();
+        while let Some(row) = rows.next_row() {
+            let row = row.unwrap();
+            wells.push(
+                row.meta_columns()
+                    .map(|(_, v)| v.to_string())
+                    .collect::<Vec<_>>(),
+            );
+            assert_eq!(
+                row.get_by_name("Well"),
+                Some(wells.last().unwrap()[0].as_str())
+            );
+        }
+        let wells = wells.iter().map(|w| w.join(" ")).collect::<Vec<_>>();
+        assert_eq!(wells, ["A01 DMSO", "A01 DMSO", " ", "A02 "]);
+        assert_eq!(rows.report().annotations.missing_wells["P1"].len(), 1);
+    }
+
+    #[test]
+    fn reading_stops_at_an_error() {
+        let dir = TestDir::new();
+        write_files(&dir);
+        let metadata = dir.metadata();
+        std::fs::remove_file(&metadata[0].path).unwrap();
+        let mut rows = Rows::new(&metadata);
+        assert!(rows.next_row().unwrap().is_err());
+        assert!(rows.next_row().is_none());
+    }
+}
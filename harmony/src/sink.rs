@@ -0,0 +1,172 @@
+use std::io::{self, Write};
+
+use crate::{info::HarmonyMetadata, write::write_interspersed};
+
+/// Destination for combined rows, driven by [`combine_into`](crate::combine_into).
+///
+/// Rows arrive in the layout chosen by the [`CombineOptions`](crate::CombineOptions),
+/// with their cells in the order of the header passed to
+/// [`begin_schema`](Self::begin_schema).
+pub trait RecordSink {
+    /// Called once, before any rows, with the output columns
+    fn begin_schema(&mut self, header: &[&str]) -> io::Result<()>;
+
+    fn write_row(&mut self, cells: &[&str]) -> io::Result<()>;
+
+    /// Called once the rows of a datafile have been written. With the wide layout, that
+    /// is after the rows its plate's files were joined into.
+    fn end_file(&mut self, _file: &HarmonyMetadata) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once, after the last file
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: RecordSink + ?Sized> RecordSink for &mut S {
+    fn begin_schema(&mut self, header: &[&str]) -> io::Result<()> {
+        (**self).begin_schema(header)
+    }
+
+    fn write_row(&mut self, cells: &[&str]) -> io::Result<()> {
+        (**self).write_row(cells)
+    }
+
+    fn end_file(&mut self, file: &HarmonyMetadata) -> io::Result<()> {
+        (**self).end_file(file)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Tab separated rows under a header line, as written by
+/// [`combine_files_with`](crate::combine_files_with)
+#[derive(Debug)]
+pub struct TsvSink<W> {
+    wtr: W,
+}
+
+impl<W: Write> TsvSink<W> {
+    pub fn new(wtr: W) -> Self {
+        Self { wtr }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+}
+
+impl<W: Write> RecordSink for TsvSink<W> {
+    fn begin_schema(&mut self, header: &[&str]) -> io::Result<()> {
+        self.write_row(header)
+    }
+
+    fn write_row(&mut self, cells: &[&str]) -> io::Result<()> {
+        write_interspersed(&mut self.wtr, cells, "\t")?;
+        writeln!(self.wtr)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_data::{Datafile, TestDir},
+        write::{combine_into, CombineOptions, Layout},
+    };
+
+    /// Every call, in order
+    #[derive(Default)]
+    struct Calls(Vec<String>);
+
+    impl RecordSink for Calls {
+        fn begin_schema(&mut self, header: &[&str]) -> io::Result<()> {
+            self.0.push(format!("schema {}", header.len()));
+            Ok(())
+        }
+
+        fn write_row(&mut self, cells: &[&str]) -> io::Result<()> {
+            self.0.push(format!("row {}", cells[0]));
+            Ok(())
+        }
+
+        fn end_file(&mut self, file: &HarmonyMetadata) -> io::Result<()> {
+            let name = file.path.file_name().unwrap().to_string_lossy();
+            self.0.push(format!("end {} {name}", file.plate_name));
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            self.0.push("finish".into());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn calls_in_order() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tCount\n1\t1\t2\n1\t2\t3\n";
+        for plate in ["P1", "P2"] {
+            dir.write(&format!("{plate}/a.txt"), Datafile::new(plate, None, data));
+            let nuclei = Datafile::new(plate, Some("Nuclei"), "Row\tColumn\tArea\n1\t1\t5\n");
+            dir.write(&format!("{plate}/b.txt"), nuclei);
+        }
+        let metadata = dir.metadata();
+
+        let mut calls = Calls::default();
+        combine_into(&mut calls, &metadata, &CombineOptions::default()).unwrap();
+        let stacked = [
+            "schema 9",
+            "row P1",
+            "row P1",
+            "end P1 a.txt",
+            "row P1",
+            "end P1 b.txt",
+            "row P2",
+            "row P2",
+            "end P2 a.txt",
+            "row P2",
+            "end P2 b.txt",
+            "finish",
+        ];
+        assert_eq!(calls.0, stacked);
+
+        // the wide layout ends a plate's files after the rows they were joined into
+        let opts = CombineOptions {
+            layout: Layout::Wide,
+            ..CombineOptions::default()
+        };
+        let mut calls = Calls::default();
+        combine_into(&mut calls, &metadata, &opts).unwrap();
+        let wide = [
+            "schema 8",
+            "row P1",
+            "row P1",
+            "end P1 a.txt",
+            "end P1 b.txt",
+            "row P2",
+            "row P2",
+            "end P2 a.txt",
+            "end P2 b.txt",
+            "finish",
+        ];
+        assert_eq!(calls.0, wide);
+    }
+
+    #[test]
+    fn tsv_lines() {
+        let mut sink = TsvSink::new(Vec::new());
+        sink.begin_schema(&["a", "b"]).unwrap();
+        sink.write_row(&["1", ""]).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(sink.into_inner(), b"a\tb\n1\t\n");
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::Path,
+};
+
+use rusqlite::{params_from_iter, types::Value, Connection};
+
+use crate::{
+    info::HarmonyMetadata,
+    utils::ValueKind,
+    write::{
+        for_each_row, population_header, population_label, CombineOptions, CombineReport,
+        MetaColumns,
+    },
+};
+
+const FILES_TABLE: &str = "files";
+const PLATES_TABLE: &str = "plates";
+const FILE_COLUMNS: &[&str] = &[
+    "id INTEGER PRIMARY KEY",
+    "plate_id INTEGER NOT NULL REFERENCES plates(id)",
+    "path TEXT NOT NULL",
+    "database_name TEXT",
+    "database_location TEXT",
+    "measurement INTEGER NOT NULL",
+    "evaluation INTEGER NOT NULL",
+    "evaluation_signature TEXT",
+    "population TEXT NOT NULL",
+];
+
+/// Write the combined data into a new SQLite database at `path`, replacing any file there.
+///
+/// The database has a `plates` table, a `files` table with one row per datafile (its
+/// path, database, plate, measurement, evaluation, population and evaluation
+/// signature, plus any plate name pattern or path template columns), and a table per
+/// population named after it. Each population row has the `file_id` and `plate_id`
+/// it came from, then the well ID and plate map columns, then the data columns.
+///
+/// Data columns are declared `INTEGER`, `REAL` or `TEXT` from the values they hold, and
+/// population tables are indexed by plate and well. The layout and aggregate options
+/// don't apply; every row is written.
+pub fn write_sqlite(
+    path: &Path,
+    metadata: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    let mut report = CombineReport::default();
+
+    let mut populations: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, m) in metadata.iter().enumerate() {
+        populations.entry(population_label(m)).or_default().push(i);
+    }
+    if let Some(pop) = populations
+        .keys()
+        .find(|p| [FILES_TABLE, PLATES_TABLE].contains(&p.to_ascii_lowercase().as_str()))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("population <{pop}> has the name of a database table"),
+        ));
+    }
+    let formats = MetaColumns::detect_formats(metadata, opts)?;
+    let schemas = populations
+        .iter()
+        .map(|(pop, files)| {
+            let hdr = population_header(files.iter().map(|&i| &metadata[i]));
+            let meta = MetaColumns::with_formats(opts, &hdr, formats.clone())?;
+            Ok((*pop, files.as_slice(), hdr, meta))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let mut conn = Connection::open(path).map_err(sql_err)?;
+    let tx = conn.transaction().map_err(sql_err)?;
+
+    // plates and files
+    let plates = metadata
+        .iter()
+        .map(|m| m.plate_name.as_str())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .zip(1..)
+        .collect::<BTreeMap<_, i64>>();
+    tx.execute_batch(&format!(
+        "CREATE TABLE {PLATES_TABLE} (id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);"
+    ))
+    .map_err(sql_err)?;
+    for (name, id) in &plates {
+        tx.execute(
+            &format!("INSERT INTO {PLATES_TABLE} (id, name) VALUES (?1, ?2)"),
+            (id, name),
+        )
+        .map_err(sql_err)?;
+    }
+
+    let derived = schemas
+        .first()
+        .map_or_else(Vec::new, |(_, _, _, meta)| meta.derived_header());
+    let mut columns = FILE_COLUMNS
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>();
+    columns.extend(derived.iter().map(|c| format!("{} TEXT", quote(c))));
+    tx.execute_batch(&format!(
+        "CREATE TABLE {FILES_TABLE} ({});\
+         CREATE INDEX {FILES_TABLE}_plate ON {FILES_TABLE} (plate_id);",
+        columns.join(", ")
+    ))
+    .map_err(sql_err)?;
+    {
+        let placeholders = vec!["?"; FILE_COLUMNS.len() + derived.len()].join(", ");
+        let mut insert = tx
+            .prepare(&format!(
+                "INSERT INTO {FILES_TABLE} VALUES ({placeholders})"
+            ))
+            .map_err(sql_err)?;
+        let meta = schemas.first().map(|s| &s.3);
+        for (m, id) in metadata.iter().zip(1..) {
+            let mut values = vec![
+                Value::Integer(id),
+                Value::Integer(plates[m.plate_name.as_str()]),
+                Value::Text(m.path.display().to_string()),
+                Value::Text(m.db_name.to_string()),
+                Value::Text(m.db_location.to_string()),
+                Value::Integer(m.measurement.into()),
+                Value::Integer(m.evaluation.into()),
+                Value::Text(m.eval_sig.clone()),
+                Value::Text(population_label(m).to_string()),
+            ];
+            if let Some(meta) = meta {
+                let fields = meta.derived_fields(m, &mut report);
+                values.extend(fields.into_iter().map(|f| typed(f, ValueKind::Text)));
+            }
+            insert.execute(params_from_iter(values)).map_err(sql_err)?;
+        }
+    }
+
+    // one table per population
+    for (pop, files, hdr, meta) in &schemas {
+        let names = meta
+            .well_header()
+            .into_iter()
+            .chain(hdr.iter().map(|h| h.as_ref()))
+            .collect::<Vec<_>>();
+
+        // typing pass; the rows are counted and checked as they are written
+        let mut kinds = vec![ValueKind::Empty; names.len()];
+        let mut typing = CombineReport::default();
+        for_each_row(metadata, files, hdr, meta, &mut typing, |_, row| {
+            for (k, v) in kinds.iter_mut().zip(row) {
+                *k = (*k).max(ValueKind::of(v));
+            }
+            Ok(())
+        })?;
+
+        let table = quote(pop);
+        let mut columns = vec![
+            format!("file_id INTEGER NOT NULL REFERENCES {FILES_TABLE}(id)"),
+            format!("plate_id INTEGER NOT NULL REFERENCES {PLATES_TABLE}(id)"),
+        ];
+        columns.extend(
+            names
+                .iter()
+                .zip(&kinds)
+                .map(|(n, k)| format!("{} {}", quote(n), sql_type(*k))),
+        );
+        tx.execute_batch(&format!("CREATE TABLE {table} ({});", columns.join(", ")))
+            .map_err(sql_err)?;
+
+        let placeholders = vec!["?"; names.len() + 2].join(", ");
+        let mut insert = tx
+            .prepare(&format!("INSERT INTO {table} VALUES ({placeholders})"))
+            .map_err(sql_err)?;
+        for_each_row(metadata, files, hdr, meta, &mut report, |i, row| {
+            let ids = [
+                Value::Integer(i as i64 + 1),
+                Value::Integer(plates[metadata[i].plate_name.as_str()]),
+            ];
+            let values = row.iter().zip(&kinds).map(|(v, &k)| typed(v, k));
+            insert
+                .execute(params_from_iter(ids.into_iter().chain(values)))
+                .map_err(sql_err)?;
+            Ok(())
+        })?;
+
+        // look rows up by file, and by plate and well
+        let mut indices = vec![("file", vec!["file_id"])];
+        if names.contains(&"Row") && names.contains(&"Column") {
+            indices.push(("row_column", vec!["plate_id", "Row", "Column"]));
+        }
+        if let Some(well) = opts.well.as_ref() {
+            indices.push(("well", vec!["plate_id", well.name.as_str()]));
+        }
+        if indices.len() == 1 {
+            indices.push(("plate", vec!["plate_id"]));
+        }
+        for (suffix, cols) in indices {
+            let cols = cols.iter().map(|c| quote(c)).collect::<Vec<_>>();
+            tx.execute_batch(&format!(
+                "CREATE INDEX {} ON {table} ({});",
+                quote(&format!("{pop}_{suffix}")),
+                cols.join(", ")
+            ))
+            .map_err(sql_err)?;
+        }
+    }
+
+    tx.commit().map_err(sql_err)?;
+    Ok(report)
+}
+
+/// A cell as a value of its column's type, with empty cells as `NULL`
+fn typed(v: &str, kind: ValueKind) -> Value {
+    if v.is_empty() {
+        return Value::Null;
+    }
+    match kind {
+        ValueKind::Integer => v.parse().map_or(Value::Null, Value::Integer),
+        ValueKind::Real => v.parse().map_or(Value::Null, Value::Real),
+        ValueKind::Empty | ValueKind::Text => Value::Text(v.to_string()),
+    }
+}
+
+fn sql_type(kind: ValueKind) -> &'static str {
+    match kind {
+        ValueKind::Integer => "INTEGER",
+        ValueKind::Real => "REAL",
+        ValueKind::Empty | ValueKind::Text => "TEXT",
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_err(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_data::{Datafile, TestDir},
+        write::WellOptions,
+    };
+
+    fn declared_types(conn: &Connection, table: &str) -> Vec<(String, String)> {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA table_info({})", quote(table)))
+            .unwrap();
+        stmt.query_map([], |r| Ok((r.get(1)?, r.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn columns_typed_by_their_values() {
+        let dir = TestDir::new();
+        let well = "Row\tColumn\tCount\tArea\tCode\tNote\n1\t1\t2\t3\t007\t\n1\t2\t4\t2.5\t12\t\n";
+        let well = dir.write("p/well.txt", Datafile::new("P1", None, well));
+        let nuclei = "Row\tColumn\tArea\n1\t1\t5\n";
+        dir.write("p/nuclei.txt", Datafile::new("P1", Some("Nuclei"), nuclei));
+
+        let db = dir.root.join("out.sqlite");
+        let opts = CombineOptions {
+            well: Some(WellOptions::default()),
+            ..CombineOptions::default()
+        };
+        let report = write_sqlite(&db, &dir.metadata(), &opts).unwrap();
+        assert_eq!(report.rows[&well], 2);
+
+        let conn = Connection::open(&db).unwrap();
+        let types = declared_types(&conn, "Well");
+        let expected = [
+            ("file_id", "INTEGER"),
+            ("plate_id", "INTEGER"),
+            ("Well", "TEXT"),
+            ("Row", "INTEGER"),
+            ("Column", "INTEGER"),
+            ("Count", "INTEGER"),
+            ("Area", "REAL"),
+            ("Code", "TEXT"),
+            ("Note", "TEXT"),
+        ]
+        .map(|(n, t)| (n.to_string(), t.to_string()));
+        assert_eq!(types, expected);
+
+        let row: (String, f64, String, Option<String>, String) = conn
+            .query_row(
+                "SELECT Well, Area, Code, Note, typeof(Area) FROM Well WHERE \"Column\" = 1",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)),
+            )
+            .unwrap();
+        assert_eq!(row, ("A01".into(), 3.0, "007".into(), None, "real".into()));
+
+        let files: Vec<(String, i64)> = conn
+            .prepare(
+                "SELECT f.population, n.file_id FROM files f JOIN Nuclei n ON n.file_id = f.id",
+            )
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(files, [("Nuclei".to_string(), 1)]);
+    }
+
+    #[test]
+    fn populations_named_like_a_table() {
+        let dir = TestDir::new();
+        dir.write(
+            "p/x.txt",
+            Datafile::new("P1", Some("Plates"), "Row\tColumn\n1\t1\n"),
+        );
+        let db = dir.root.join("out.sqlite");
+        assert!(write_sqlite(&db, &dir.metadata(), &CombineOptions::default()).is_err());
+    }
+}
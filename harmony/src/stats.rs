@@ -0,0 +1,112 @@
+// summary statistics over plain f64 slices
+// NaNs should be filtered out by the caller, and each function returns NaN for too few values
+
+pub(crate) fn mean(xs: &[f64]) -> f64 {
+    if xs.is_empty() {
+        return f64::NAN;
+    }
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+/// Sample standard deviation
+pub(crate) fn std_dev(xs: &[f64]) -> f64 {
+    if xs.len() < 2 {
+        return f64::NAN;
+    }
+    let m = mean(xs);
+    let ss = xs.iter().map(|x| (x - m).powi(2)).sum::<f64>();
+    (ss / (xs.len() - 1) as f64).sqrt()
+}
+
+/// Quantile of already sorted values, interpolating linearly between the closest ranks
+pub(crate) fn quantile_sorted(sorted: &[f64], q: f64) -> f64 {
+    match sorted.len() {
+        0 => f64::NAN,
+        1 => sorted[0],
+        n => {
+            let pos = q.clamp(0.0, 1.0) * (n - 1) as f64;
+            let lo = pos.floor() as usize;
+            let hi = pos.ceil() as usize;
+            sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+        }
+    }
+}
+
+pub(crate) fn sort(xs: &mut [f64]) {
+    xs.sort_unstable_by(|a, b| a.total_cmp(b));
+}
+
+pub(crate) fn median(xs: &[f64]) -> f64 {
+    let mut sorted = xs.to_vec();
+    sort(&mut sorted);
+    quantile_sorted(&sorted, 0.5)
+}
+
+/// Scales the MAD of normally distributed values to their standard deviation
+pub(crate) const MAD_SCALE: f64 = 1.4826;
+
+/// Median absolute deviation from the median, unscaled
+pub(crate) fn mad(xs: &[f64]) -> f64 {
+    let m = median(xs);
+    let deviations = xs.iter().map(|x| (x - m).abs()).collect::<Vec<_>>();
+    median(&deviations)
+}
+
+/// Parse a data cell as a finite number
+pub(crate) fn parse_value(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+/// Two-sided 95% critical value of Student's t distribution
+pub(crate) fn t_critical_95(df: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    match df {
+        0 => f64::NAN,
+        1..=30 => TABLE[df - 1],
+        // within 0.003 of the exact value past 30 degrees of freedom
+        _ => 1.96 + 2.4 / df as f64,
+    }
+}
+
+/// Pearson correlation of paired values
+pub(crate) fn pearson(xs: &[f64], ys: &[f64]) -> f64 {
+    let (mx, my) = (mean(xs), mean(ys));
+    let mut sxy = 0.0;
+    let mut sxx = 0.0;
+    let mut syy = 0.0;
+    for (x, y) in xs.iter().zip(ys) {
+        sxy += (x - mx) * (y - my);
+        sxx += (x - mx).powi(2);
+        syy += (y - my).powi(2);
+    }
+    sxy / (sxx * syy).sqrt()
+}
+
+/// Spearman rank correlation, with tied values given their average rank
+pub(crate) fn spearman(xs: &[f64], ys: &[f64]) -> f64 {
+    pearson(&ranks(xs), &ranks(ys))
+}
+
+fn ranks(xs: &[f64]) -> Vec<f64> {
+    let mut order = (0..xs.len()).collect::<Vec<_>>();
+    order.sort_unstable_by(|&a, &b| xs[a].total_cmp(&xs[b]));
+    let mut ranks = vec![0.0; xs.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && xs[order[end]] == xs[order[start]] {
+            end += 1;
+        }
+        // ranks are 1-based, so the tied ranks start+1..=end average to this
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &i in &order[start..end] {
+            ranks[i] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+    sync::Arc,
+};
+
+use crate::{
+    info::HarmonyMetadata,
+    sink::{RecordSink, TsvSink},
+    stats::parse_value,
+    well::{Well, COLUMN_HDR, ROW_HDR},
+    write::{combine_into, CombineOptions, CombineReport},
+};
+
+pub const PLATE_HDR: &str = "Plate Name";
+pub(crate) const TIMEPOINT_HDR: &str = "Timepoint";
+const WELL_HDR: &str = "Well";
+/// Columns that keep the rows of separately analysed plates apart, when the table has them
+pub(crate) const PLATE_GROUP_HDRS: &[&str] =
+    &[PLATE_HDR, "Measurement", "Evaluation", "Population"];
+
+/// Combined rows held in memory, for analyses that need more than one pass over a plate.
+///
+/// Meant for well-level data; use [`AggregateOptions`](crate::AggregateOptions) first to
+/// bring object-level exports down to a manageable size.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub header: Vec<Arc<str>>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Combine files the same way as [`combine_files_with`](crate::combine_files_with), but
+/// keep the result in memory
+pub fn combine_table(
+    metadata: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<(Table, CombineReport)> {
+    let mut table = Table::default();
+    let report = combine_into(&mut table, metadata, opts)?;
+    Ok((table, report))
+}
+
+/// Collects the combined rows, replacing anything the table held
+impl RecordSink for Table {
+    fn begin_schema(&mut self, header: &[&str]) -> io::Result<()> {
+        self.header = header.iter().map(|&h| Arc::from(h)).collect();
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn write_row(&mut self, cells: &[&str]) -> io::Result<()> {
+        self.rows
+            .push(cells.iter().map(|&c| c.to_string()).collect());
+        Ok(())
+    }
+}
+
+impl Table {
+    /// Read a tab separated table with a header row, such as a combined output file
+    pub fn read_tsv(rdr: impl BufRead) -> io::Result<Self> {
+        let mut lines = rdr.lines();
+        let header = match lines.next() {
+            Some(line) => line?
+                .trim_end_matches('\r')
+                .split('\t')
+                .map(Arc::from)
+                .collect(),
+            None => return Ok(Self::default()),
+        };
+        let rows = lines
+            .map(|line| {
+                line.map(|l| {
+                    l.trim_end_matches('\r')
+                        .split('\t')
+                        .map(String::from)
+                        .collect()
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self { header, rows })
+    }
+
+    pub fn write_tsv(&self, wtr: impl Write) -> io::Result<()> {
+        self.write_to(TsvSink::new(wtr))
+    }
+
+    /// Hand the header and rows to a sink, the same way [`combine_into`] does
+    pub fn write_to(&self, mut sink: impl RecordSink) -> io::Result<()> {
+        let header = self.header.iter().map(|h| h.as_ref()).collect::<Vec<_>>();
+        sink.begin_schema(&header)?;
+        let mut cells = Vec::with_capacity(header.len());
+        for row in &self.rows {
+            cells.clear();
+            cells.extend(row.iter().map(String::as_str));
+            // short rows are padded, as missing cells read as empty
+            cells.resize(header.len().max(row.len()), "");
+            sink.write_row(&cells)?;
+        }
+        sink.finish()
+    }
+
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|h| h.as_ref() == name)
+    }
+
+    /// Value of a cell, with missing cells of short rows read as empty
+    pub fn cell(&self, row: usize, column: usize) -> &str {
+        self.rows[row].get(column).map_or("", String::as_str)
+    }
+
+    /// Insert a column at a position, shifting the columns after it to the right
+    pub fn insert_column(&mut self, at: usize, name: &str, values: Vec<String>) {
+        self.header.insert(at, Arc::from(name));
+        for (row, v) in self.rows.iter_mut().zip(values) {
+            if row.len() < at {
+                row.resize(at, String::new());
+            }
+            row.insert(at, v);
+        }
+    }
+
+    /// Keep only the rows of plates that pass the check, such as plates that passed QC.
+    ///
+    /// Plates are told apart by their values of the plate name, measurement,
+    /// evaluation and population columns the table has, in that order, the same way
+    /// as in [`QcReport::group_columns`](crate::QcReport::group_columns).
+    pub fn retain_plates(&mut self, mut keep: impl FnMut(&[&str]) -> bool) {
+        let idx = PLATE_GROUP_HDRS
+            .iter()
+            .filter_map(|c| self.column(c))
+            .collect::<Vec<_>>();
+        self.rows.retain(|row| {
+            let key = idx
+                .iter()
+                .map(|&i| row.get(i).map_or("", String::as_str))
+                .collect::<Vec<_>>();
+            keep(&key)
+        });
+    }
+
+    /// Whether any of the rows has a number in a feature's column.
+    ///
+    /// A stacked table holds the rows of every population, and only the feature's own
+    /// population has values for it, so groups without any are skipped rather than
+    /// reported as missing the feature.
+    pub(crate) fn has_feature(&self, col: usize, rows: impl IntoIterator<Item = usize>) -> bool {
+        rows.into_iter()
+            .any(|r| parse_value(self.cell(r, col)).is_some())
+    }
+
+    /// Find the plate name and well of every row
+    pub(crate) fn locator(&self) -> io::Result<Locator> {
+        let plate = self
+            .column(PLATE_HDR)
+            .ok_or_else(|| missing_column(PLATE_HDR))?;
+        let well = match (self.column(ROW_HDR), self.column(COLUMN_HDR)) {
+            (Some(r), Some(c)) => WellSource::RowColumn(r, c),
+            _ => WellSource::Id(
+                self.column(WELL_HDR)
+                    .ok_or_else(|| missing_column("Row/Column or Well"))?,
+            ),
+        };
+        Ok(Locator { plate, well })
+    }
+
+    /// Row indices grouped by the values of the named columns that the table has
+    pub(crate) fn group_by(&self, columns: &[&str]) -> BTreeMap<Vec<&str>, Vec<usize>> {
+        let idx = columns
+            .iter()
+            .filter_map(|c| self.column(c))
+            .collect::<Vec<_>>();
+        let mut groups: BTreeMap<Vec<&str>, Vec<usize>> = BTreeMap::new();
+        for row in 0..self.rows.len() {
+            let key = idx.iter().map(|&i| self.cell(row, i)).collect();
+            groups.entry(key).or_default().push(row);
+        }
+        groups
+    }
+}
+
+/// Where the plate name and well of a table row come from
+pub(crate) struct Locator {
+    plate: usize,
+    well: WellSource,
+}
+
+enum WellSource {
+    RowColumn(usize, usize),
+    Id(usize),
+}
+
+impl Locator {
+    pub(crate) fn plate<'a>(&self, table: &'a Table, row: usize) -> &'a str {
+        table.cell(row, self.plate)
+    }
+
+    pub(crate) fn well(&self, table: &Table, row: usize) -> Option<Well> {
+        match self.well {
+            WellSource::RowColumn(r, c) => {
+                Well::from_fields(table.cell(row, r), table.cell(row, c))
+            }
+            WellSource::Id(i) => table.cell(row, i).parse().ok(),
+        }
+    }
+}
+
+pub(crate) fn missing_column(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("combined data has no {name} column"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        combine_files_with,
+        test_data::{Datafile, TestDir},
+        write::Layout,
+    };
+
+    #[test]
+    fn table_holds_what_the_tsv_has() {
+        let dir = TestDir::new();
+        let well = "Row\tColumn\tCount\n1\t1\t2\n1\t2\t\n";
+        dir.write("p/well.txt", Datafile::new("P1", None, well));
+        let nuclei = "Row\tColumn\tArea\n1\t1\t5\n";
+        dir.write("p/nuclei.txt", Datafile::new("P1", Some("Nuclei"), nuclei));
+        let metadata = dir.metadata();
+
+        for layout in [Layout::Stacked, Layout::Wide, Layout::Long] {
+            let opts = CombineOptions {
+                layout,
+                ..CombineOptions::default()
+            };
+            let mut tsv = Vec::new();
+            combine_files_with(&mut tsv, &metadata, &opts).unwrap();
+            let expected = Table::read_tsv(tsv.as_slice()).unwrap();
+
+            let (table, report) = combine_table(&metadata, &opts).unwrap();
+            assert_eq!(table.header, expected.header);
+            assert_eq!(table.rows, expected.rows);
+            assert_eq!(report.rows.values().sum::<usize>(), 3);
+        }
+    }
+}
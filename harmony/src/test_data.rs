@@ -0,0 +1,114 @@
+//! Harmony datafiles written to a temporary directory for tests
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    info::{collect_harmony_datafiles, HarmonyMetadata},
+    platemap::PlateMap,
+    table::Table,
+};
+
+/// A directory of datafiles, removed when dropped
+pub(crate) struct TestDir {
+    pub root: PathBuf,
+}
+
+/// The metadata and data lines of one datafile
+#[derive(Clone, Copy)]
+pub(crate) struct Datafile<'a> {
+    pub plate: &'a str,
+    pub measurement: u32,
+    pub evaluation: u32,
+    /// `None` for well-level results
+    pub population: Option<&'a str>,
+    /// tab separated header and rows, one per line
+    pub data: &'a str,
+}
+
+impl<'a> Datafile<'a> {
+    pub fn new(plate: &'a str, population: Option<&'a str>, data: &'a str) -> Self {
+        Self {
+            plate,
+            measurement: 1,
+            evaluation: 1,
+            population,
+            data,
+        }
+    }
+}
+
+impl TestDir {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let root = std::env::temp_dir().join(format!("harmony-test-{}-{n}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        Self { root }
+    }
+
+    /// Write a datafile at `path` below the root
+    pub fn write(&self, path: &str, file: Datafile) -> PathBuf {
+        let path = self.root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let mut text = format!(
+            "Database Name\tHarmonyDB\nDatabase Location\tdb\nEvaluation Signature\tsig\n\
+             Plate Name\t{}\nMeasurement\tMeasurement {}\nEvaluation\tEvaluation{}\n",
+            file.plate, file.measurement, file.evaluation
+        );
+        if let Some(pop) = file.population {
+            text.push_str(&format!("Population\t{pop}\n"));
+        }
+        text.push_str("\n[Data]\n");
+        text.push_str(file.data);
+        if !file.data.ends_with('\n') {
+            text.push('\n');
+        }
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    /// The datafiles below the root, in path order
+    pub fn metadata(&self) -> Vec<HarmonyMetadata> {
+        let mut md = collect_harmony_datafiles(&self.root);
+        md.sort_by(|a, b| a.path.cmp(&b.path));
+        md
+    }
+
+    /// Write a CSV plate map below the root and read it back
+    pub fn plate_map(&self, csv: &str) -> PlateMap {
+        let path = self.root.join("map.csv");
+        fs::write(&path, csv).unwrap();
+        PlateMap::from_files([path]).unwrap()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Text written to a `Vec`, as a string
+pub(crate) fn utf8(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap()
+}
+
+/// Lines of tab separated text as rows of cells
+pub(crate) fn cells(text: &str) -> Vec<Vec<&str>> {
+    text.lines().map(|l| l.split('\t').collect()).collect()
+}
+
+/// A table from lines of tab separated text, the first of them the header
+pub(crate) fn table(text: &str) -> Table {
+    let mut lines = cells(text).into_iter();
+    let header = lines.next().unwrap().into_iter().map(Into::into).collect();
+    let rows = lines
+        .map(|l| l.into_iter().map(str::to_string).collect())
+        .collect();
+    Table { header, rows }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    stats::parse_value,
+    table::{missing_column, Table, PLATE_HDR, TIMEPOINT_HDR},
+    well::{COLUMN_HDR, ROW_HDR},
+};
+
+const MEASUREMENT_HDR: &str = "Measurement";
+const FIELD_HDR: &str = "Field";
+const POPULATION_HDR: &str = "Population";
+const TIME_INDEX_HDR: &str = "Time Index";
+const ELAPSED_HDR: &str = "Elapsed [h]";
+
+/// Where the elapsed time of each timepoint comes from
+#[derive(Debug, Clone)]
+pub enum TimeSource {
+    /// timepoints are this far apart
+    Interval(Duration),
+    /// a data column with acquisition times, either in seconds or as
+    /// `YYYY-MM-DD HH:MM:SS` date-times; a timepoint starts at its earliest row
+    Column(String),
+}
+
+/// Order the repeated measurements of each plate into a time course
+#[derive(Debug, Clone)]
+pub struct TimeCourseOptions {
+    pub time: TimeSource,
+    /// features to spread into one column per timepoint, or a tidy table when empty
+    pub wide_features: Vec<String>,
+}
+
+/// Parse an interval such as `90s`, `15m`, `1.5h` or `2d`; bare numbers are seconds
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let scale = match unit.trim() {
+        "" | "s" | "sec" => 1.0,
+        "m" | "min" => 60.0,
+        "h" | "hr" => 3600.0,
+        "d" | "day" => 86400.0,
+        _ => return Err(format!("unknown time unit in <{s}>, expected s, m, h or d")),
+    };
+    num.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite() && *n > 0.0)
+        .map(|n| Duration::from_secs_f64(n * scale))
+        .ok_or_else(|| format!("invalid interval <{s}>"))
+}
+
+/// Seconds since the unix epoch of a `YYYY-MM-DD[T ]HH:MM:SS[.fff]` date-time, or a plain number
+fn parse_timestamp(s: &str) -> Option<f64> {
+    let s = s.trim();
+    if let Some(v) = parse_value(s) {
+        return Some(v);
+    }
+    let (date, time) = s.split_once(['T', ' '])?;
+    let mut ymd = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (y, m, d) = (ymd.next()??, ymd.next()??, ymd.next()??);
+    let time = time.trim_end_matches('Z');
+    let mut hms = time.splitn(3, ':');
+    let h = hms.next()?.parse::<f64>().ok()?;
+    let min = hms.next()?.parse::<f64>().ok()?;
+    let sec = hms.next().map_or(Some(0.0), |s| s.parse::<f64>().ok())?;
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+
+    // days from civil, counting years from March so leap days come last
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(days as f64 * 86400.0 + h * 3600.0 + min * 60.0 + sec)
+}
+
+/// Order the measurements and timepoints of each plate and add when each was taken.
+///
+/// A timepoint is a distinct `Measurement` and `Timepoint` pair of a plate, so both
+/// repeated measurements and harmony's own kinetic timepoints are handled. Each row
+/// gets a `Time Index` (from 0) and `Elapsed [h]` since the plate's first timepoint,
+/// and the rows are sorted by plate, well and time. With `wide_features`, the table
+/// becomes one row per well instead, with a `{feature} - T{index}` column per timepoint.
+pub fn time_course(table: &mut Table, opts: &TimeCourseOptions) -> io::Result<()> {
+    let locator = table.locator()?;
+    let plate_col = table
+        .column(PLATE_HDR)
+        .ok_or_else(|| missing_column(PLATE_HDR))?;
+    let time_cols = [MEASUREMENT_HDR, TIMEPOINT_HDR]
+        .iter()
+        .filter_map(|h| table.column(h))
+        .collect::<Vec<_>>();
+    let stamp_col = match &opts.time {
+        TimeSource::Column(c) => Some(table.column(c).ok_or_else(|| missing_column(c))?),
+        TimeSource::Interval(_) => None,
+    };
+
+    // the start of every timepoint of every plate
+    let mut starts: BTreeMap<&str, BTreeMap<Vec<&str>, f64>> = BTreeMap::new();
+    for row in 0..table.rows.len() {
+        let key = time_cols.iter().map(|&c| table.cell(row, c)).collect();
+        let stamp = stamp_col
+            .and_then(|c| parse_timestamp(table.cell(row, c)))
+            .unwrap_or(f64::NAN);
+        let start = starts
+            .entry(table.cell(row, plate_col))
+            .or_default()
+            .entry(key)
+            .or_insert(f64::NAN);
+        *start = start.min(stamp);
+    }
+
+    // time index and elapsed hours of every timepoint
+    let mut times: HashMap<(&str, Vec<&str>), (usize, f64)> = HashMap::new();
+    for (plate, points) in &starts {
+        let mut points = points.iter().collect::<Vec<_>>();
+        match &opts.time {
+            // measurement and timepoint numbers, compared as numbers
+            TimeSource::Interval(_) => points.sort_by(|a, b| {
+                let num = |k: &[&str]| k.iter().map(|v| parse_value(v)).collect::<Vec<_>>();
+                num(a.0)
+                    .partial_cmp(&num(b.0))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            }),
+            TimeSource::Column(_) => points.sort_by(|a, b| a.1.total_cmp(b.1)),
+        }
+        let first = points.first().map_or(f64::NAN, |p| *p.1);
+        for (i, (key, start)) in points.into_iter().enumerate() {
+            let elapsed = match &opts.time {
+                TimeSource::Interval(d) => d.as_secs_f64() * i as f64,
+                TimeSource::Column(_) => start - first,
+            };
+            times.insert((*plate, key.clone()), (i, elapsed / 3600.0));
+        }
+    }
+
+    let row_times = (0..table.rows.len())
+        .map(|row| {
+            let key = time_cols.iter().map(|&c| table.cell(row, c)).collect();
+            times[&(table.cell(row, plate_col), key)]
+        })
+        .collect::<Vec<_>>();
+
+    // sort by plate, population, well and time, keeping the order of anything else
+    let pop_col = table.column(POPULATION_HDR);
+    let mut order = (0..table.rows.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&r| {
+        (
+            table.cell(r, plate_col),
+            pop_col.map(|c| table.cell(r, c)),
+            locator.well(table, r),
+            row_times[r].0,
+        )
+    });
+
+    if !opts.wide_features.is_empty() {
+        *table = spread(table, &order, &row_times, &opts.wide_features)?;
+        return Ok(());
+    }
+
+    let mut rows = std::mem::take(&mut table.rows);
+    let mut sorted = Vec::with_capacity(rows.len());
+    let mut index = Vec::with_capacity(rows.len());
+    let mut elapsed = Vec::with_capacity(rows.len());
+    for r in order {
+        sorted.push(std::mem::take(&mut rows[r]));
+        index.push(row_times[r].0.to_string());
+        elapsed.push(format_hours(row_times[r].1));
+    }
+    table.rows = sorted;
+
+    // the time columns go after the timepoint, or the measurement when there isn't one
+    let at = time_cols.last().map_or(plate_col, |&c| c) + 1;
+    table.insert_column(at, TIME_INDEX_HDR, index);
+    table.insert_column(at + 1, ELAPSED_HDR, elapsed);
+    Ok(())
+}
+
+fn format_hours(h: f64) -> String {
+    if h.is_finite() {
+        h.to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// Build the wide table: the descriptive columns before `Row`, without the
+/// measurement, then the well and field, then each feature at each timepoint
+fn spread(
+    table: &Table,
+    order: &[usize],
+    row_times: &[(usize, f64)],
+    features: &[String],
+) -> io::Result<Table> {
+    let row_col = table
+        .column(ROW_HDR)
+        .ok_or_else(|| missing_column(ROW_HDR))?;
+    let mut key_cols = (0..row_col)
+        .filter(|&c| table.header[c].as_ref() != MEASUREMENT_HDR)
+        .collect::<Vec<_>>();
+    key_cols.extend(
+        [ROW_HDR, COLUMN_HDR, FIELD_HDR]
+            .iter()
+            .filter_map(|h| table.column(h)),
+    );
+    let feature_cols = features
+        .iter()
+        .map(|f| table.column(f).ok_or_else(|| missing_column(f)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let n_times = row_times.iter().map(|t| t.0 + 1).max().unwrap_or(0);
+
+    let mut header = key_cols
+        .iter()
+        .map(|&c| Arc::clone(&table.header[c]))
+        .collect::<Vec<_>>();
+    for f in features {
+        header.extend((0..n_times).map(|t| Arc::from(format!("{f} - T{t}"))));
+    }
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut index: HashMap<Vec<&str>, usize> = HashMap::new();
+    for &r in order {
+        let key = key_cols
+            .iter()
+            .map(|&c| table.cell(r, c))
+            .collect::<Vec<_>>();
+        let out = *index.entry(key).or_insert_with_key(|key| {
+            let mut row = key.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            row.resize(key.len() + features.len() * n_times, String::new());
+            rows.push(row);
+            rows.len() - 1
+        });
+        let t = row_times[r].0;
+        for (i, &c) in feature_cols.iter().enumerate() {
+            let value = table.cell(r, c);
+            if !value.is_empty() {
+                rows[out][key_cols.len() + i * n_times + t] = value.to_string();
+            }
+        }
+    }
+
+    Ok(Table { header, rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals() {
+        let secs = |s| parse_interval(s).map(|d| d.as_secs_f64());
+        assert_eq!(secs("90s"), Ok(90.0));
+        assert_eq!(secs("30"), Ok(30.0));
+        assert_eq!(secs(" 15 min "), Ok(900.0));
+        assert_eq!(secs("1.5h"), Ok(5400.0));
+        assert_eq!(secs("2d"), Ok(172800.0));
+
+        assert!(secs("").is_err());
+        assert!(secs("0s").is_err());
+        assert!(secs("-5m").is_err());
+        assert!(secs("NaNh").is_err());
+        assert!(secs("5w").unwrap_err().contains("unknown time unit"));
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01 00:00:00"), Some(0.0));
+        assert_eq!(parse_timestamp(" 12.5 "), Some(12.5));
+        // 2000-03-01 is 11017 days after the epoch, past a leap day
+        assert_eq!(
+            parse_timestamp("2000-03-01T12:30:15.5Z"),
+            Some(11017.0 * 86400.0 + 45015.5)
+        );
+        // seconds may be left out
+        assert_eq!(
+            parse_timestamp("2024-02-29 08:15"),
+            Some(19782.0 * 86400.0 + 29700.0)
+        );
+        // before the epoch
+        assert_eq!(parse_timestamp("1969-12-31 23:59:59"), Some(-1.0));
+
+        assert_eq!(parse_timestamp("2024-01-01"), None);
+        assert_eq!(parse_timestamp("2024-13-01 00:00:00"), None);
+        assert_eq!(parse_timestamp("2024-01-32 00:00:00"), None);
+        assert_eq!(parse_timestamp("2024-01-01 noon"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
@@ -6,10 +6,88 @@ use std::{
     sync::Arc,
 };
 
+use crate::info::HarmonyMetadata;
+#[cfg(any(feature = "arrow", feature = "sqlite"))]
+use crate::rows::Value;
+
 pub(crate) fn read_lines<P: AsRef<Path>>(p: P) -> io::Result<Lines<BufReader<File>>> {
     File::open(p).map(|f| BufReader::new(f).lines())
 }
 
+/// Open a harmony datafile with the reader positioned at the first data row
+pub(crate) fn open_data(md: &HarmonyMetadata) -> io::Result<BufReader<File>> {
+    let mut rdr = File::open(&md.path).map(BufReader::new)?;
+    let mut buf = String::with_capacity(0x400);
+    let mut line = 0;
+    while line < md.data_start && rdr.read_line(&mut buf)? != 0 {
+        buf.clear();
+        line += 1;
+    }
+    Ok(rdr)
+}
+
+/// Number of non-empty data rows in a datafile
+pub(crate) fn count_rows(md: &HarmonyMetadata) -> io::Result<usize> {
+    let mut rdr = open_data(md)?;
+    let mut buf = String::with_capacity(0x400);
+    let mut n = 0;
+    while rdr.read_line(&mut buf)? != 0 {
+        if data_line(&buf).is_some() {
+            n += 1;
+        }
+        buf.clear();
+    }
+    Ok(n)
+}
+
+/// A line of a datafile without its line ending, or `None` if it is empty and so not
+/// a row. Lines of only spaces or tabs are rows of empty cells.
+pub(crate) fn data_line(buf: &str) -> Option<&str> {
+    Some(buf.trim_end_matches(['\r', '\n'])).filter(|line| !line.is_empty())
+}
+
+/// Position of a named column in a header row
+pub(crate) fn column_index(headers: &[Arc<str>], name: &str) -> Option<usize> {
+    headers.iter().position(|h| h.as_ref() == name)
+}
+
+/// Whether a number is written with leading zeros, like the identifier `007`, and
+/// should be kept as text
+pub(crate) fn has_leading_zero(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.")
+}
+
+/// Narrowest type that holds every value of a column
+#[cfg(any(feature = "arrow", feature = "sqlite"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ValueKind {
+    Empty,
+    Integer,
+    Real,
+    Text,
+}
+
+#[cfg(any(feature = "arrow", feature = "sqlite"))]
+impl ValueKind {
+    pub(crate) fn of(v: &str) -> Self {
+        match Value::parse(v) {
+            Value::Null => Self::Empty,
+            Value::Integer(_) => Self::Integer,
+            Value::Real(_) => Self::Real,
+            Value::Text(_) => Self::Text,
+        }
+    }
+}
+
+/// Escape text for use in HTML or SVG
+pub(crate) fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 pub(crate) struct StrIntern(HashSet<Arc<str>>);
 
 impl StrIntern {
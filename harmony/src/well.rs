@@ -0,0 +1,311 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    io::{self, BufRead},
+    str::FromStr,
+};
+
+use crate::{
+    info::HarmonyMetadata,
+    utils::{column_index, open_data},
+};
+
+/// Name of the harmony column holding the 1-based well row
+pub const ROW_HDR: &str = "Row";
+/// Name of the harmony column holding the 1-based well column
+pub const COLUMN_HDR: &str = "Column";
+
+/// A well position on a plate, with 1-based row and column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Well {
+    pub row: u32,
+    pub column: u32,
+}
+
+impl Well {
+    pub fn new(row: u32, column: u32) -> Self {
+        Self { row, column }
+    }
+
+    /// Build a well from the text of harmony's `Row` and `Column` fields
+    pub fn from_fields(row: &str, column: &str) -> Option<Self> {
+        let row = row.trim().parse().ok().filter(|&r| r > 0)?;
+        let column = column.trim().parse().ok().filter(|&c| c > 0)?;
+        Some(Self { row, column })
+    }
+
+    /// Row letters, e.g. `A` for row 1 and `AF` for row 32
+    pub fn row_label(&self) -> String {
+        let mut n = self.row;
+        let mut letters = Vec::with_capacity(2);
+        while n > 0 {
+            n -= 1;
+            letters.push(b'A' + (n % 26) as u8);
+            n /= 26;
+        }
+        letters.reverse();
+        String::from_utf8(letters).expect("only ascii letters in row labels")
+    }
+
+    /// Format the well as an ID, e.g. `A01` or `A1` depending on the style
+    pub fn label(&self, style: WellStyle, format: Option<PlateFormat>) -> String {
+        let width = match (style, format) {
+            (WellStyle::Unpadded, _) => 0,
+            (WellStyle::Padded, Some(f)) => f.column_digits(),
+            (WellStyle::Padded, None) => 2,
+        };
+        format!("{}{:0width$}", self.row_label(), self.column, width = width)
+    }
+}
+
+impl fmt::Display for Well {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{:02}", self.row_label(), self.column)
+    }
+}
+
+impl FromStr for Well {
+    type Err = String;
+
+    /// Parse IDs such as `A1`, `a01` or `AF48`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(s.len());
+        let (letters, digits) = s.split_at(split);
+
+        if letters.is_empty() || letters.len() > 2 {
+            return Err(format!("<{s}> does not start with a row letter"));
+        }
+        let row = letters.bytes().fold(0, |acc, b| {
+            acc * 26 + u32::from(b.to_ascii_uppercase() - b'A' + 1)
+        });
+        let column = digits
+            .parse()
+            .ok()
+            .filter(|&c| c > 0)
+            .ok_or_else(|| format!("<{s}> does not end with a column number"))?;
+
+        Ok(Self { row, column })
+    }
+}
+
+/// How a well ID is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WellStyle {
+    /// zero-pad the column to the width of the plate's largest column, e.g. `A01`
+    #[default]
+    Padded,
+    /// no padding, e.g. `A1`
+    Unpadded,
+}
+
+impl FromStr for WellStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "padded" => Ok(Self::Padded),
+            "unpadded" => Ok(Self::Unpadded),
+            _ => Err(format!(
+                "unknown well style <{s}>, expected padded or unpadded"
+            )),
+        }
+    }
+}
+
+/// Standard SBS plate layouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PlateFormat {
+    Wells96,
+    Wells384,
+    Wells1536,
+}
+
+impl PlateFormat {
+    const ALL: [Self; 3] = [Self::Wells96, Self::Wells384, Self::Wells1536];
+
+    pub const fn rows(self) -> u32 {
+        match self {
+            Self::Wells96 => 8,
+            Self::Wells384 => 16,
+            Self::Wells1536 => 32,
+        }
+    }
+
+    pub const fn columns(self) -> u32 {
+        match self {
+            Self::Wells96 => 12,
+            Self::Wells384 => 24,
+            Self::Wells1536 => 48,
+        }
+    }
+
+    pub const fn wells(self) -> u32 {
+        self.rows() * self.columns()
+    }
+
+    /// Smallest format that can hold a well at the given row and column
+    pub fn detect(max_row: u32, max_column: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|f| max_row <= f.rows() && max_column <= f.columns())
+    }
+
+    /// Smallest format that can hold all of the wells
+    pub fn fitting<'a>(wells: impl IntoIterator<Item = &'a Well>) -> Option<Self> {
+        let (max_row, max_col) = wells
+            .into_iter()
+            .fold((0, 0), |(r, c), w| (r.max(w.row), c.max(w.column)));
+        (max_row > 0).then(|| Self::detect(max_row, max_col))?
+    }
+
+    pub fn contains(self, well: Well) -> bool {
+        well.row <= self.rows() && well.column <= self.columns()
+    }
+
+    fn column_digits(self) -> usize {
+        self.columns().to_string().len()
+    }
+}
+
+impl fmt::Display for PlateFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-well", self.wells())
+    }
+}
+
+impl FromStr for PlateFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let n = s.trim().trim_end_matches("-well");
+        Self::ALL
+            .into_iter()
+            .find(|f| f.wells().to_string() == n)
+            .ok_or_else(|| format!("unknown plate format <{s}>, expected 96, 384 or 1536"))
+    }
+}
+
+/// Find the plate format of every plate by reading the `Row` and `Column` of each datafile.
+///
+/// The smallest format that holds every well seen on a plate is chosen, so a sparsely
+/// used plate may be detected as a smaller format. Plates whose wells do not fit
+/// any format, or whose files lack `Row`/`Column`, are left out.
+pub fn detect_plate_formats(
+    metadata: &[HarmonyMetadata],
+) -> io::Result<BTreeMap<String, PlateFormat>> {
+    Ok(collect_plate_wells(metadata)?
+        .into_iter()
+        .filter_map(|(plate, wells)| PlateFormat::fitting(&wells).map(|f| (plate, f)))
+        .collect())
+}
+
+/// Find the wells that have data on each plate from the `Row` and `Column` of each datafile.
+///
+/// Files without `Row`/`Column` columns are skipped.
+pub fn collect_plate_wells(
+    metadata: &[HarmonyMetadata],
+) -> io::Result<BTreeMap<String, BTreeSet<Well>>> {
+    let mut plates: BTreeMap<String, BTreeSet<Well>> = BTreeMap::new();
+    let mut buf = String::with_capacity(0x400);
+
+    for md in metadata {
+        let (ridx, cidx) = match (
+            column_index(&md.headers, ROW_HDR),
+            column_index(&md.headers, COLUMN_HDR),
+        ) {
+            (Some(r), Some(c)) => (r, c),
+            _ => continue,
+        };
+        let wells = plates.entry(md.plate_name.clone()).or_default();
+
+        let mut rdr = open_data(md)?;
+        while rdr.read_line(&mut buf)? != 0 {
+            let fields = buf
+                .trim_end_matches(['\r', '\n'])
+                .split('\t')
+                .collect::<Vec<_>>();
+            let well = fields
+                .get(ridx)
+                .zip(fields.get(cidx))
+                .and_then(|(r, c)| Well::from_fields(r, c));
+            if let Some(w) = well {
+                wells.insert(w);
+            }
+            buf.clear();
+        }
+    }
+
+    Ok(plates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{Datafile, TestDir};
+
+    #[test]
+    fn two_letter_rows_round_trip() {
+        let well = "AF48".parse::<Well>().unwrap();
+        assert_eq!(well, Well::new(32, 48));
+        assert_eq!(well.row_label(), "AF");
+        assert_eq!(well.to_string(), "AF48");
+        assert_eq!("af48".parse(), Ok(well));
+        assert_eq!(
+            well.label(WellStyle::Padded, Some(PlateFormat::Wells1536)),
+            "AF48"
+        );
+        assert_eq!(well.label(WellStyle::Unpadded, None), "AF48");
+    }
+
+    #[test]
+    fn single_letter_rows() {
+        let well = "a1".parse::<Well>().unwrap();
+        assert_eq!(well, Well::new(1, 1));
+        assert_eq!(well.to_string(), "A01");
+        assert_eq!("A01".parse(), Ok(well));
+        assert_eq!(
+            well.label(WellStyle::Padded, Some(PlateFormat::Wells96)),
+            "A01"
+        );
+        assert_eq!(well.label(WellStyle::Unpadded, None), "A1");
+        assert_eq!(Well::new(26, 3).row_label(), "Z");
+        assert_eq!(Well::new(27, 3).row_label(), "AA");
+    }
+
+    #[test]
+    fn invalid_ids() {
+        assert!("".parse::<Well>().is_err());
+        assert!("12".parse::<Well>().is_err());
+        assert!("ABC1".parse::<Well>().is_err());
+        assert!("A0".parse::<Well>().is_err());
+        assert!("A".parse::<Well>().is_err());
+    }
+
+    #[test]
+    fn smallest_format_holding_every_well() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tArea\n1\t1\t5\n16\t24\t6\n";
+        dir.write("a/well.txt", Datafile::new("P384", None, data));
+        dir.write(
+            "b/well.txt",
+            Datafile::new("P96", None, "Row\tColumn\n8\t12\n"),
+        );
+        dir.write(
+            "c/well.txt",
+            Datafile::new("Huge", None, "Row\tColumn\n33\t1\n"),
+        );
+        dir.write("d/well.txt", Datafile::new("NoWells", None, "Area\n5\n"));
+
+        let formats = detect_plate_formats(&dir.metadata()).unwrap();
+        assert_eq!(
+            formats.into_iter().collect::<Vec<_>>(),
+            [
+                ("P384".to_string(), PlateFormat::Wells384),
+                ("P96".to_string(), PlateFormat::Wells96)
+            ]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+    sync::Arc,
+};
+
+use crate::{
+    info::HarmonyMetadata,
+    rows::Rows,
+    sink::RecordSink,
+    utils::column_index,
+    well::{COLUMN_HDR, ROW_HDR},
+    write::{population_label, CombineOptions, CombineReport, MetaColumns},
+};
+
+/// Columns that identify a well-level row, in output order.
+/// `Row` and `Column` are required, the others are used when every file has them.
+const KEY_HDRS: &[&str] = &[ROW_HDR, COLUMN_HDR, "Field", "Timepoint"];
+
+/// Join the populations of each plate, measurement and evaluation into one row per well.
+///
+/// Rows are matched on the key columns (`Row`, `Column`, and `Field`/`Timepoint` when
+/// every file has them), and every other column is prefixed with its population name,
+/// e.g. `Nuclei - Area`, unless harmony already named it that way. Populations with
+/// more than one row for the same key (such as object-level exports) only keep the
+/// first row; the rest are counted in [`CombineReport::duplicate_rows`].
+pub(crate) fn write_wide(
+    sink: &mut impl RecordSink,
+    md: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    let mut report = CombineReport::default();
+
+    let keys = KEY_HDRS
+        .iter()
+        .copied()
+        .filter(|k| md.iter().all(|m| column_index(&m.headers, k).is_some()))
+        .collect::<Vec<_>>();
+    if !keys.contains(&ROW_HDR) || !keys.contains(&COLUMN_HDR) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "every file needs Row and Column data columns to join populations by well",
+        ));
+    }
+    let key_hdr = keys
+        .iter()
+        .map(|&k| Arc::from(k))
+        .collect::<Vec<Arc<str>>>();
+    let meta = MetaColumns::new(md, opts, &key_hdr)?;
+
+    // find the feature columns of each population, and where they go in the output
+    let mut pops: BTreeMap<&str, Vec<Arc<str>>> = BTreeMap::new();
+    for m in md {
+        let features = pops.entry(population_label(m)).or_default();
+        for h in &m.headers {
+            if !keys.contains(&h.as_ref()) && !features.contains(h) {
+                features.push(Arc::clone(h));
+            }
+        }
+    }
+    let mut offsets = HashMap::new();
+    let mut n_features = 0;
+    for (pop, features) in &pops {
+        offsets.insert(*pop, n_features);
+        n_features += features.len();
+    }
+
+    // group the files that are joined together
+    let mut groups: BTreeMap<(&str, u32, u32), Vec<&HarmonyMetadata>> = BTreeMap::new();
+    for m in md {
+        groups
+            .entry((&m.plate_name, m.measurement, m.evaluation))
+            .or_default()
+            .push(m);
+    }
+
+    let mut prefixed = Vec::with_capacity(n_features);
+    for (pop, features) in &pops {
+        let prefix = format!("{pop} - ");
+        for f in features {
+            // harmony names many features after their population already
+            if f.starts_with(&prefix) {
+                prefixed.push(f.to_string());
+            } else {
+                prefixed.push(format!("{prefix}{f}"));
+            }
+        }
+    }
+    let mut header = meta.header(false);
+    header.extend(&keys);
+    header.extend(prefixed.iter().map(String::as_str));
+    sink.begin_schema(&header)?;
+
+    for ((plate, _, _), files) in groups {
+        let common_info = meta.file_values(files[0], false, &mut report);
+
+        // rows in order of first appearance, keyed by the key column values
+        let mut rows: Vec<(Vec<String>, Vec<String>)> = Vec::new();
+        let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+
+        for &m in &files {
+            let pop = population_label(m);
+            let offset = offsets[pop];
+            let features = &pops[pop];
+            let columns = key_hdr.iter().chain(features).cloned().collect();
+            let mut seen = HashSet::new();
+            let mut joined = 0;
+
+            let mut lines = Rows::with_header([m], columns);
+            while let Some(line) = lines.next_row() {
+                let line = line?;
+                let key = (0..keys.len())
+                    .map(|i| line.cell(i).to_string())
+                    .collect::<Vec<_>>();
+
+                if !seen.insert(key.clone()) {
+                    *report.duplicate_rows.entry(pop.to_string()).or_default() += 1;
+                    continue;
+                }
+                let row = *index.entry(key).or_insert_with_key(|key| {
+                    rows.push((key.clone(), vec![String::new(); n_features]));
+                    rows.len() - 1
+                });
+                joined += 1;
+                let cells = &mut rows[row].1;
+                for (i, out) in cells[offset..offset + features.len()]
+                    .iter_mut()
+                    .enumerate()
+                {
+                    if let Some(data) = line.get(keys.len() + i) {
+                        *out = data.to_string();
+                    }
+                }
+            }
+            report.add_rows(m, joined);
+        }
+
+        for (key, cells) in &rows {
+            let key_fields = key.iter().map(String::as_str).collect::<Vec<_>>();
+            let well = meta.well(&key_fields);
+
+            let well_values = meta.well_values(plate, well, &mut report);
+            let row = common_info
+                .iter()
+                .chain(&well_values)
+                .chain(key)
+                .chain(cells)
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            sink.write_row(&row)?;
+        }
+        for m in files {
+            sink.end_file(m)?;
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_data::{cells, utf8, Datafile, TestDir},
+        write::{combine_files_with, CombineOptions, CombineReport, Layout},
+    };
+
+    fn wide(dir: &TestDir) -> std::io::Result<(String, CombineReport)> {
+        let opts = CombineOptions {
+            layout: Layout::Wide,
+            ..CombineOptions::default()
+        };
+        let mut out = Vec::new();
+        let report = combine_files_with(&mut out, &dir.metadata(), &opts)?;
+        Ok((utf8(out), report))
+    }
+
+    #[test]
+    fn populations_joined_by_well() {
+        let dir = TestDir::new();
+        let well = "Row\tColumn\tCount\n1\t1\t2\n1\t2\t3\n";
+        dir.write("p1/well.txt", Datafile::new("P1", None, well));
+        let nuclei = "Row\tColumn\tArea\tNuclei - Count\n1\t2\t5\t1\n1\t3\t6\t1\n1\t2\t7\t2\n";
+        dir.write("p1/nuclei.txt", Datafile::new("P1", Some("Nuclei"), nuclei));
+        dir.write(
+            "p2/well.txt",
+            Datafile::new("P2", None, "Row\tColumn\tCount\n2\t1\t9\n"),
+        );
+
+        let (out, report) = wide(&dir).unwrap();
+        let rows = cells(&out);
+        assert_eq!(
+            rows[0][4..],
+            [
+                "Row",
+                "Column",
+                "Nuclei - Area",
+                "Nuclei - Count",
+                "Well - Count"
+            ]
+        );
+        let data = rows[1..]
+            .iter()
+            .map(|r| (r[0], &r[4..]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            data,
+            [
+                ("P1", &["1", "2", "5", "1", "3"][..]),
+                ("P1", &["1", "3", "6", "1", ""][..]),
+                ("P1", &["1", "1", "", "", "2"][..]),
+                ("P2", &["2", "1", "", "", "9"][..]),
+            ]
+        );
+        assert_eq!(report.duplicate_rows["Nuclei"], 1);
+    }
+
+    #[test]
+    fn keys_every_file_has() {
+        let dir = TestDir::new();
+        let fields = "Row\tColumn\tField\tCount\n1\t1\t1\t2\n1\t1\t2\t3\n";
+        dir.write("p/fields.txt", Datafile::new("P1", Some("Fields"), fields));
+        dir.write(
+            "p/well.txt",
+            Datafile::new("P1", None, "Row\tColumn\tCount\n1\t1\t5\n"),
+        );
+
+        // only some files have a Field column, so it is joined as a feature
+        let (out, report) = wide(&dir).unwrap();
+        let rows = cells(&out);
+        assert_eq!(
+            rows[0][4..],
+            [
+                "Row",
+                "Column",
+                "Fields - Field",
+                "Fields - Count",
+                "Well - Count"
+            ]
+        );
+        assert_eq!(rows[1][4..], ["1", "1", "1", "2", "5"]);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(report.duplicate_rows["Fields"], 1);
+
+        dir.write(
+            "p/other.txt",
+            Datafile::new("P1", Some("Other"), "Well\tArea\nA01\t1\n"),
+        );
+        assert!(wide(&dir).is_err());
+    }
+}
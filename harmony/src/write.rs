@@ -1,20 +1,104 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, BTreeSet, HashSet},
     fmt::Display,
-    fs::File,
-    io::{self, BufRead, BufReader, Write},
+    io::{self, Write},
     ops::Deref,
-    path::Path,
+    path::PathBuf,
     sync::Arc,
 };
 
-use crate::info::HarmonyMetadata;
+use crate::{
+    aggregate::{write_aggregated, AggregateOptions},
+    fields::{PathTemplate, PlateNamePattern},
+    info::HarmonyMetadata,
+    long::write_long,
+    platemap::{AnnotationReport, PlateMap},
+    rows::Rows,
+    sink::{RecordSink, TsvSink},
+    utils::column_index,
+    well::{detect_plate_formats, PlateFormat, Well, WellStyle, COLUMN_HDR, ROW_HDR},
+    wide::write_wide,
+};
+
+/// Extra processing applied while combining files
+#[derive(Debug, Clone, Default)]
+pub struct CombineOptions {
+    /// add a well ID column built from the `Row` and `Column` data columns
+    pub well: Option<WellOptions>,
+    /// left-join plate map annotations onto each row by plate name and well
+    pub plate_map: Option<PlateMap>,
+    /// split plate names into extra metadata columns with named capture groups
+    pub plate_pattern: Option<PlateNamePattern>,
+    /// name the directories between the search root and each datafile as extra metadata columns
+    pub path_template: Option<PathTemplate>,
+    pub layout: Layout,
+    /// summarise each well's rows instead of writing them all out
+    pub aggregate: Option<AggregateOptions>,
+}
+
+/// Shape of the combined output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// rows of every file stacked on top of each other, with the union of all columns
+    #[default]
+    Stacked,
+    /// populations of the same plate, measurement and evaluation joined into one row per well
+    Wide,
+    /// one row per feature value, with `Feature`, `Value` and `Unit` columns
+    Long,
+}
+
+#[derive(Debug, Clone)]
+pub struct WellOptions {
+    /// name of the added column
+    pub name: String,
+    pub style: WellStyle,
+    /// plate format every plate should have, instead of detecting each plate's format
+    /// from its wells; wells outside it are counted in [`CombineReport::outside_format`]
+    pub format: Option<PlateFormat>,
+}
+
+impl Default for WellOptions {
+    fn default() -> Self {
+        Self {
+            name: "Well".into(),
+            style: WellStyle::default(),
+            format: None,
+        }
+    }
+}
+
+pub fn combine_files(out: impl Write, metadata: &[HarmonyMetadata]) -> io::Result<()> {
+    combine_files_with(out, metadata, &CombineOptions::default()).map(|_| ())
+}
+
+pub fn combine_files_with(
+    out: impl Write,
+    metadata: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    combine_into(TsvSink::new(out), metadata, opts)
+}
 
-pub fn combine_files(mut out: impl Write, metadata: &[HarmonyMetadata]) -> io::Result<()> {
-    let test = combine_headers(metadata);
-    // println!("{:#?}", test);
-    // let mut stdout = io::stdout().lock();
-    write_output(&mut out, metadata, test)
+/// Combine files the same way as [`combine_files_with`], but hand each row to a sink
+/// instead of writing text
+pub fn combine_into(
+    mut sink: impl RecordSink,
+    metadata: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    let report = match (opts.layout, &opts.aggregate) {
+        (Layout::Stacked, Some(agg)) => write_aggregated(&mut sink, metadata, opts, agg),
+        (_, Some(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "aggregated output can only use the stacked layout",
+        )),
+        (Layout::Stacked, None) => write_output(&mut sink, metadata, opts),
+        (Layout::Wide, None) => write_wide(&mut sink, metadata, opts),
+        (Layout::Long, None) => write_long(&mut sink, metadata, opts),
+    }?;
+    sink.finish()?;
+    Ok(report)
 }
 
 // combine all headers to find all columns
@@ -24,137 +108,441 @@ pub fn combine_files(mut out: impl Write, metadata: &[HarmonyMetadata]) -> io::R
 // create output file and write header
 // loop thru each file and copy data?
 
-fn combine_headers(metadata: &[HarmonyMetadata]) -> Option<Combined> {
+/// Header of the stacked output: the first file's columns, then any other columns
+pub(crate) fn combined_header(metadata: &[HarmonyMetadata]) -> Vec<Arc<str>> {
     let mut iter = metadata
         .iter()
         .map(|m| m.headers.iter().map(Arc::clone).collect::<HashSet<_>>());
-    if let Some(base) = iter.next() {
-        let differences: HashSet<Arc<str>> = iter.fold(HashSet::new(), |mut diffs, other| {
-            diffs.extend(base.symmetric_difference(&other).cloned());
-            diffs
-        });
+    let base = match iter.next() {
+        Some(base) => base,
+        None => return Vec::new(),
+    };
+    let differences: HashSet<Arc<str>> = iter.fold(HashSet::new(), |mut diffs, other| {
+        diffs.extend(base.symmetric_difference(&other).cloned());
+        diffs
+    });
 
-        if differences.is_empty() {
-            return None;
-        }
-        // creating canonical header
-        // find the columns in differences that are not in base
-        // this gets rid of any columns that were in the base file, but were not in a later file
-        let new_hdrs = {
-            let mut n = differences.difference(&base).cloned().collect::<Vec<_>>();
-            n.sort();
-            metadata[0]
-                .headers
-                .iter()
-                .cloned()
-                .chain(n)
-                .collect::<Vec<_>>()
-        };
-        // create map between original file headers and the combined new headers
-        let maps = metadata
+    // creating canonical header
+    // find the columns in differences that are not in base
+    // this gets rid of any columns that were in the base file, but were not in a later file
+    let mut n = differences.difference(&base).cloned().collect::<Vec<_>>();
+    n.sort();
+    metadata[0].headers.iter().cloned().chain(n).collect()
+}
+
+fn write_output(
+    sink: &mut impl RecordSink,
+    md: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    let mut report = CombineReport::default();
+    let mut rows = Rows::new(md);
+    let hdr = rows.header().to_vec();
+    let meta = MetaColumns::new(md, opts, &hdr)?;
+
+    // common fields, then derived fields, then data headers
+    let mut header = meta.header(true);
+    header.extend(hdr.iter().map(|h| h.as_ref()));
+    sink.begin_schema(&header)?;
+
+    let files = md
+        .iter()
+        .map(|m| meta.file_values(m, true, &mut report))
+        .collect::<Vec<_>>();
+    // files before this one have been written
+    let mut next_file = 0;
+    let mut counts = vec![0; md.len()];
+    while let Some(row) = rows.next_row() {
+        let row = row?;
+        for m in &md[next_file..row.file] {
+            sink.end_file(m)?;
+        }
+        next_file = row.file;
+        counts[row.file] += 1;
+
+        let data = row.cells().collect::<Vec<_>>();
+        let well = meta.well(&data);
+        let well_values = meta.well_values(&row.metadata.plate_name, well, &mut report);
+        let output_line = files[row.file]
             .iter()
-            .map(|m| {
-                m.headers
-                    .iter()
-                    .map(
-                        |orig| match new_hdrs.iter().position(|s| orig.deref() == s.deref()) {
-                            Some(idx) => idx,
-                            None => {
-                                eprintln!("missing\t<{}>\t in:", orig);
-                                eprintln!("{:#?}", &new_hdrs);
-                                panic!("new combined headers should contain all headers");
-                            }
-                        },
-                    )
-                    .collect()
-            })
-            .collect();
-
-        Some(Combined {
-            hdr: new_hdrs,
-            maps,
-        })
-    } else {
-        None
+            .chain(&well_values)
+            .map(String::as_str)
+            .chain(data)
+            .collect::<Vec<_>>();
+        sink.write_row(&output_line)?;
+    }
+    for m in &md[next_file..] {
+        sink.end_file(m)?;
+    }
+    for (m, n) in md.iter().zip(counts) {
+        report.add_rows(m, n);
     }
+
+    Ok(report)
 }
 
+/// Metadata columns written before the data columns: the common fields, fields derived
+/// from the plate name and path, then the well ID and plate map annotations
 #[derive(Debug)]
-struct Combined {
-    hdr: Vec<Arc<str>>,
-    maps: Vec<Vec<usize>>,
+pub(crate) struct MetaColumns<'a> {
+    opts: &'a CombineOptions,
+    well_idx: Option<WellIndex>,
+    wells: Option<WellColumn<'a>>,
+    /// format of each plate, used where [`WellOptions::format`] doesn't give one
+    formats: BTreeMap<String, PlateFormat>,
 }
 
-fn write_output(
-    wtr: &mut impl Write,
-    md: &[HarmonyMetadata],
-    combination: Option<Combined>,
-) -> io::Result<()> {
-    let mut buf = String::with_capacity(0x400);
-    // write headers for output fil
-    // common fields then data headers
-    write_interspersed(wtr, COMMON_FIELD_HDR, "\t")?;
-    write!(wtr, "\t")?;
-    let hdr = combination
-        .as_ref()
-        .map(|c| c.hdr.as_slice())
-        .or(md.get(0).map(|f| f.headers.as_slice()))
-        .expect("at least one header");
-    write_interspersed(wtr, hdr, "\t")?;
-    writeln!(wtr)?;
-
-    if let Some(comb) = combination {
-        // open file
-        for (md, map) in md.iter().zip(comb.maps.iter()) {
-            // generate common field
-            let common_info = generate_common_fields(md, "\t");
-
-            // open file and skip ahead to data
-            let mut rdr = open_bufread(&md.path)?;
-            let mut line = 0;
-            while line < md.data_start && rdr.read_line(&mut buf).is_ok() {
-                buf.clear();
-                line += 1;
+impl<'a> MetaColumns<'a> {
+    /// `hdr` is the data header that rows passed to [`MetaColumns::well`] follow.
+    ///
+    /// Without a format in the well options, the format of every plate is detected
+    /// first, which reads each of the `metadata` files once.
+    pub(crate) fn new(
+        metadata: &[HarmonyMetadata],
+        opts: &'a CombineOptions,
+        hdr: &[Arc<str>],
+    ) -> io::Result<Self> {
+        Self::with_formats(opts, hdr, Self::detect_formats(metadata, opts)?)
+    }
+
+    /// Formats of the plates of `metadata`, for [`with_formats`](Self::with_formats),
+    /// or none when there is no well column or its format is given
+    pub(crate) fn detect_formats(
+        metadata: &[HarmonyMetadata],
+        opts: &CombineOptions,
+    ) -> io::Result<BTreeMap<String, PlateFormat>> {
+        match &opts.well {
+            Some(w) if w.format.is_none() => detect_plate_formats(metadata),
+            _ => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Like [`new`](Self::new), with plate formats already detected, for writers that
+    /// need the columns of several headers
+    pub(crate) fn with_formats(
+        opts: &'a CombineOptions,
+        hdr: &[Arc<str>],
+        formats: BTreeMap<String, PlateFormat>,
+    ) -> io::Result<Self> {
+        let well_idx = (opts.well.is_some() || opts.plate_map.is_some())
+            .then(|| WellIndex::new(hdr))
+            .transpose()?;
+        let wells = opts.well.as_ref().map(|w| WellColumn {
+            name: &w.name,
+            style: w.style,
+            format: w.format,
+        });
+
+        Ok(Self {
+            opts,
+            well_idx,
+            wells,
+            formats,
+        })
+    }
+
+    pub(crate) fn header(&self, population: bool) -> Vec<&str> {
+        let mut hdr = COMMON_FIELD_HDR
+            .iter()
+            .copied()
+            .filter(|&h| population || h != POPULATION_HDR)
+            .collect::<Vec<_>>();
+        hdr.extend(self.derived_header());
+        hdr.extend(self.well_header());
+        hdr
+    }
+
+    /// Columns derived from the plate name and path, the same for every row of a file
+    pub(crate) fn derived_header(&self) -> Vec<&str> {
+        let mut hdr = Vec::new();
+        if let Some(pat) = &self.opts.plate_pattern {
+            hdr.extend(pat.columns().iter().map(Deref::deref));
+        }
+        if let Some(tmpl) = &self.opts.path_template {
+            hdr.extend(tmpl.columns().iter().map(Deref::deref));
+        }
+        hdr
+    }
+
+    /// The well ID and plate map annotation columns
+    pub(crate) fn well_header(&self) -> Vec<&str> {
+        let mut hdr = Vec::new();
+        if let Some(w) = &self.wells {
+            hdr.push(w.name);
+        }
+        if let Some(pm) = &self.opts.plate_map {
+            hdr.extend(pm.columns().iter().map(Deref::deref));
+        }
+        hdr
+    }
+
+    /// Common and derived fields that are the same for every row of a file
+    pub(crate) fn file_values(
+        &self,
+        md: &HarmonyMetadata,
+        population: bool,
+        report: &mut CombineReport,
+    ) -> Vec<String> {
+        let mut values = vec![
+            md.plate_name.clone(),
+            md.measurement.to_string(),
+            md.evaluation.to_string(),
+            md.eval_sig.clone(),
+        ];
+        if population {
+            values.push(population_label(md).to_string());
+        }
+        let derived = self.derived_fields(md, report);
+        values.extend(derived.into_iter().map(str::to_string));
+        values
+    }
+
+    /// Values of the [`derived_header`](Self::derived_header) columns for a file
+    pub(crate) fn derived_fields<'m>(
+        &self,
+        md: &'m HarmonyMetadata,
+        report: &mut CombineReport,
+    ) -> Vec<&'m str> {
+        let mut extra = Vec::new();
+        if let Some(pat) = &self.opts.plate_pattern {
+            match pat.fields(&md.plate_name) {
+                Some(fields) => extra.extend(fields),
+                None => {
+                    report.unmatched_plates.insert(md.plate_name.clone());
+                    extra.resize(pat.columns().len(), "");
+                }
             }
-            // read each line, then map the data into the output order, then write
-            buf.clear();
-            while rdr.read_line(&mut buf)? != 0 {
-                // todo: can this be moved somewhere else to avoid lifetime issues?
-                let mut output_line = vec![""; comb.hdr.len()];
-                for (data, outidx) in buf.split('\t').zip(map.iter().copied()) {
-                    output_line[outidx] = data;
+        }
+        if let Some(tmpl) = &self.opts.path_template {
+            match tmpl.fields(&md.path) {
+                Some(fields) => extra.extend(fields),
+                None => {
+                    report.unmatched_paths.push(md.path.clone());
+                    extra.resize(extra.len() + tmpl.columns().len(), "");
                 }
-                write!(wtr, "{common_info}\t")?;
-                write_interspersed(wtr, &output_line, "\t")?;
-                writeln!(wtr)?;
-                buf.clear();
             }
         }
-    } else {
-        for md in md {
-            // generate common field
-            let common_info = generate_common_fields(md, "\t");
-            // open file and skip ahead to data
-            let mut rdr = open_bufread(&md.path)?;
-            let mut line = 0;
-            while line < md.data_start && rdr.read_line(&mut buf).is_ok() {
-                buf.clear();
-                line += 1;
+        extra
+    }
+
+    pub(crate) fn well(&self, line: &[&str]) -> Option<Well> {
+        self.well_idx.as_ref().and_then(|w| w.well(line))
+    }
+
+    /// Values of the [`well_header`](Self::well_header) columns for a row
+    pub(crate) fn well_values(
+        &self,
+        plate: &str,
+        well: Option<Well>,
+        report: &mut CombineReport,
+    ) -> Vec<String> {
+        let mut values = Vec::new();
+        if let Some(w) = &self.wells {
+            let format = w.format.or_else(|| self.formats.get(plate).copied());
+            if let Some(well) = well {
+                // a plate detected as no format has wells beyond the largest one
+                if !format.unwrap_or(PlateFormat::Wells1536).contains(well) {
+                    let wells = report.outside_format.entry(plate.to_string());
+                    wells.or_default().insert(well);
+                }
             }
-            // read each line, then output common fields + data fields
-            buf.clear();
-            while rdr.read_line(&mut buf)? != 0 {
-                let line = buf.trim();
-                writeln!(wtr, "{common_info}\t{line}")?;
-                buf.clear();
+            let id = well.map(|well| well.label(w.style, format));
+            values.push(id.unwrap_or_default());
+        }
+        if let Some(pm) = &self.opts.plate_map {
+            match well.and_then(|w| pm.get(plate, w)) {
+                Some(ann) => values.extend_from_slice(ann),
+                None => {
+                    if let Some(w) = well {
+                        report.annotations.record(pm, plate, w);
+                    }
+                }
+            }
+            values.resize(self.well_header().len(), String::new());
+        }
+        values
+    }
+}
+
+/// Union of the columns of a population's files, in the order they are first seen.
+///
+/// Used with [`for_each_row`] by the writers with a table or sheet per population,
+/// which each need their own columns rather than the one header a [`RecordSink`] gets.
+#[cfg(any(feature = "sqlite", feature = "xlsx"))]
+pub(crate) fn population_header<'a>(
+    files: impl Iterator<Item = &'a HarmonyMetadata>,
+) -> Vec<Arc<str>> {
+    let mut hdr: Vec<Arc<str>> = Vec::new();
+    for m in files {
+        for h in &m.headers {
+            if !hdr.contains(h) {
+                hdr.push(Arc::clone(h));
             }
         }
     }
+    hdr
+}
 
+/// Call `f` with the index of the file and the well fields then data cells of every row
+#[cfg(any(feature = "sqlite", feature = "xlsx"))]
+pub(crate) fn for_each_row(
+    metadata: &[HarmonyMetadata],
+    files: &[usize],
+    hdr: &[Arc<str>],
+    meta: &MetaColumns,
+    report: &mut CombineReport,
+    mut f: impl FnMut(usize, &[&str]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut rows = Rows::with_header(files.iter().map(|&i| &metadata[i]), hdr.to_vec());
+    let mut counts = vec![0; files.len()];
+    while let Some(row) = rows.next_row() {
+        let row = row?;
+        counts[row.file] += 1;
+        let cells = row.cells().collect::<Vec<_>>();
+        let well = meta.well(&cells);
+        let plate = &row.metadata.plate_name;
+        let well_values = meta.well_values(plate, well, report);
+        let values = well_values
+            .iter()
+            .map(String::as_str)
+            .chain(cells)
+            .collect::<Vec<_>>();
+        f(files[row.file], &values)?;
+    }
+    for (&i, n) in files.iter().zip(counts) {
+        report.add_rows(&metadata[i], n);
+    }
     Ok(())
 }
 
-fn write_interspersed(w: &mut impl Write, items: &[impl Display], sep: &str) -> io::Result<()> {
+/// What happened while combining files
+#[derive(Debug, Clone, Default)]
+pub struct CombineReport {
+    /// plates and wells without plate map annotations
+    pub annotations: AnnotationReport,
+    /// plate names that did not match the plate name pattern
+    pub unmatched_plates: BTreeSet<String>,
+    /// datafiles whose directories did not fit the path template
+    pub unmatched_paths: Vec<PathBuf>,
+    /// rows dropped from each population by the wide layout because their well key repeated
+    pub duplicate_rows: BTreeMap<String, usize>,
+    /// wells outside the plate format given in [`WellOptions::format`] or detected, by
+    /// plate
+    pub outside_format: BTreeMap<String, BTreeSet<Well>>,
+    /// data rows of each datafile that went into the output, by path; rows the wide
+    /// layout dropped aren't counted, and aggregated rows are counted before summarising
+    pub rows: BTreeMap<PathBuf, usize>,
+}
+
+impl CombineReport {
+    /// One line for each thing worth a warning: plates and paths the patterns didn't
+    /// fit, missing annotations, wells outside the plate format and dropped rows
+    pub fn warnings(&self) -> Vec<String> {
+        let ann = &self.annotations;
+        let mut warnings = Vec::new();
+        for plate in &self.unmatched_plates {
+            warnings.push(format!(
+                "plate name <{plate}> does not match the plate name pattern"
+            ));
+        }
+        for p in &self.unmatched_paths {
+            warnings.push(format!("{} does not fit the path template", p.display()));
+        }
+        for plate in &ann.missing_plates {
+            warnings.push(format!("no plate map annotations for plate <{plate}>"));
+        }
+        for (plate, wells) in &ann.missing_wells {
+            warnings.push(format!(
+                "{} without annotations on plate <{plate}>: {}",
+                plural(wells.len(), "well"),
+                list_wells(wells)
+            ));
+        }
+        for (plate, wells) in &self.outside_format {
+            warnings.push(format!(
+                "{} outside the plate format on plate <{plate}>: {}",
+                plural(wells.len(), "well"),
+                list_wells(wells)
+            ));
+        }
+        for (pop, &n) in &self.duplicate_rows {
+            warnings.push(format!(
+                "dropped {} of population <{pop}> that repeat a well",
+                plural(n, "row")
+            ));
+        }
+        warnings
+    }
+
+    pub(crate) fn add_rows(&mut self, file: &HarmonyMetadata, n: usize) {
+        *self.rows.entry(file.path.clone()).or_default() += n;
+    }
+}
+
+/// Wells named in a warning before the rest are only counted
+const LISTED_WELLS: usize = 12;
+
+/// The first [`LISTED_WELLS`] wells of a set, and how many more there are
+fn list_wells(wells: &BTreeSet<Well>) -> String {
+    let listed = wells
+        .iter()
+        .take(LISTED_WELLS)
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>();
+    let mut text = listed.join(", ");
+    if wells.len() > listed.len() {
+        text.push_str(&format!(" and {} more", wells.len() - listed.len()));
+    }
+    text
+}
+
+fn plural(n: usize, noun: &str) -> String {
+    format!("{n} {noun}{}", if n == 1 { "" } else { "s" })
+}
+
+/// Position of the `Row` and `Column` data columns in the combined header
+#[derive(Debug)]
+struct WellIndex {
+    row: usize,
+    column: usize,
+}
+
+impl WellIndex {
+    fn new(hdr: &[Arc<str>]) -> io::Result<Self> {
+        match (column_index(hdr, ROW_HDR), column_index(hdr, COLUMN_HDR)) {
+            (Some(row), Some(column)) => Ok(Self { row, column }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no Row and Column data columns to find wells from",
+            )),
+        }
+    }
+
+    fn well(&self, line: &[&str]) -> Option<Well> {
+        line.get(self.row)
+            .zip(line.get(self.column))
+            .and_then(|(r, c)| Well::from_fields(r, c))
+    }
+}
+
+/// Well ID column derived from the `Row` and `Column` data columns
+#[derive(Debug)]
+struct WellColumn<'a> {
+    name: &'a str,
+    style: WellStyle,
+    format: Option<PlateFormat>,
+}
+
+/// Population name of a datafile, with well-level files named `Well`
+pub(crate) fn population_label(md: &HarmonyMetadata) -> &str {
+    md.population.as_deref().unwrap_or("Well")
+}
+
+pub(crate) fn write_interspersed(
+    w: &mut impl Write,
+    items: &[impl Display],
+    sep: &str,
+) -> io::Result<()> {
     let mut need_sep = false;
 
     for item in items {
@@ -169,31 +557,113 @@ fn write_interspersed(w: &mut impl Write, items: &[impl Display], sep: &str) ->
     Ok(())
 }
 
-fn open_bufread(p: &Path) -> io::Result<BufReader<File>> {
-    File::open(p).map(BufReader::new)
-}
+const POPULATION_HDR: &str = "Population";
+
+/// Metadata columns that hold text even when it looks like a number, such as a numeric
+/// plate barcode
+pub(crate) const TEXT_FIELD_HDRS: &[&str] = &["Plate Name", "Evaluation Signature", POPULATION_HDR];
 
 const COMMON_FIELD_HDR: &[&str] = &[
     "Plate Name",
     "Measurement",
     "Evaluation",
     "Evaluation Signature",
-    "Population",
+    POPULATION_HDR,
 ];
 
-fn generate_common_fields(md: &HarmonyMetadata, sep: &str) -> String {
-    let measurement = format!("{}", md.measurement);
-    let eval = format!("{}", md.evaluation);
-    let common_info = &[
-        md.plate_name.as_str(),
-        measurement.as_str(),
-        eval.as_str(),
-        md.eval_sig.as_str(),
-        md.population.as_deref().unwrap_or("Well"),
-    ];
-
-    let mut out = Vec::with_capacity(0x100);
-    write_interspersed(&mut out, common_info, sep).expect("no io issues with memory writes");
-
-    String::from_utf8(out).expect("only utf8 for common fields")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{cells, utf8, Datafile, TestDir};
+
+    fn combine(dir: &TestDir, opts: &CombineOptions) -> (String, CombineReport) {
+        let mut out = Vec::new();
+        let report = combine_files_with(&mut out, &dir.metadata(), opts).unwrap();
+        (utf8(out), report)
+    }
+
+    fn well_opts(format: Option<PlateFormat>) -> CombineOptions {
+        CombineOptions {
+            well: Some(WellOptions {
+                format,
+                ..WellOptions::default()
+            }),
+            ..CombineOptions::default()
+        }
+    }
+
+    #[test]
+    fn well_ids_follow_the_metadata_columns() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\tArea\n1\t1\t5\n16\t24\t6\n\t\t7\n";
+        dir.write("p/well.txt", Datafile::new("P1", None, data));
+
+        let (out, report) = combine(&dir, &well_opts(None));
+        let rows = cells(&out);
+        let header = [
+            "Plate Name",
+            "Measurement",
+            "Evaluation",
+            "Evaluation Signature",
+            "Population",
+            "Well",
+            "Row",
+            "Column",
+            "Area",
+        ];
+        assert_eq!(rows[0], header);
+        let wells = rows[1..].iter().map(|r| r[5]).collect::<Vec<_>>();
+        assert_eq!(wells, ["A01", "P24", ""]);
+        // detected as 384 wells, which every well fits
+        assert!(report.outside_format.is_empty());
+    }
+
+    #[test]
+    fn wells_outside_the_format() {
+        let dir = TestDir::new();
+        let data = "Row\tColumn\n1\t1\n16\t24\n";
+        dir.write("a/well.txt", Datafile::new("P1", None, data));
+        dir.write(
+            "b/well.txt",
+            Datafile::new("P2", None, "Row\tColumn\n33\t2\n"),
+        );
+
+        // a given format is checked on every plate
+        let (_, report) = combine(&dir, &well_opts(Some(PlateFormat::Wells96)));
+        let outside = |plate: &str| report.outside_format.get(plate).cloned();
+        assert_eq!(outside("P1"), Some(BTreeSet::from([Well::new(16, 24)])));
+        assert_eq!(outside("P2"), Some(BTreeSet::from([Well::new(33, 2)])));
+
+        // a plate whose wells fit no format has wells outside the largest
+        let (out, report) = combine(&dir, &well_opts(None));
+        assert_eq!(report.outside_format.len(), 1);
+        assert_eq!(
+            report.outside_format["P2"],
+            BTreeSet::from([Well::new(33, 2)])
+        );
+        assert_eq!(cells(&out)[3][5], "AG02");
+    }
+
+    #[test]
+    fn warnings_list_a_dozen_wells() {
+        let mut report = CombineReport::default();
+        report.unmatched_plates.insert("odd".into());
+        let wells = (1..=14).map(|c| Well::new(2, c)).collect();
+        report.outside_format.insert("P1".into(), wells);
+        report
+            .outside_format
+            .insert("P2".into(), BTreeSet::from([Well::new(9, 1)]));
+        report.duplicate_rows.insert("Nuclei".into(), 1);
+
+        assert_eq!(
+            report.warnings(),
+            [
+                "plate name <odd> does not match the plate name pattern",
+                "14 wells outside the plate format on plate <P1>: B01, B02, B03, B04, B05, \
+                 B06, B07, B08, B09, B10, B11, B12 and 2 more",
+                "1 well outside the plate format on plate <P2>: I01",
+                "dropped 1 row of population <Nuclei> that repeat a well",
+            ]
+        );
+    }
 }
@@ -0,0 +1,240 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io,
+    path::Path,
+};
+
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+
+use crate::{
+    info::HarmonyMetadata,
+    utils::has_leading_zero,
+    write::{
+        for_each_row, population_header, population_label, CombineOptions, CombineReport,
+        MetaColumns,
+    },
+};
+
+/// Rows of a worksheet, including its header
+const MAX_ROWS: u32 = 1_048_576;
+/// Excel's limit on the length of a sheet name
+const MAX_NAME: usize = 31;
+const FILES_SHEET: &str = "Files";
+const FILE_HDRS: &[&str] = &[
+    "Path",
+    "Plate Name",
+    "Measurement",
+    "Evaluation",
+    "Evaluation Signature",
+    "Population",
+    "Database Name",
+    "Database Location",
+    "Rows",
+];
+
+/// Write the combined data to an Excel workbook at `path`.
+///
+/// The first sheet lists the source files and their row counts, then each population
+/// gets a sheet of its rows, with the header row frozen. Numbers are written as numeric
+/// cells, except ones with leading zeros, which stay text. A population with more rows
+/// than fit on a sheet continues on sheets named `{population} (2)` and so on, and so
+/// does a population whose sheet name, once shortened and cleaned, matches another's
+/// regardless of case. The layout and aggregate options don't apply.
+pub fn write_xlsx(
+    path: &Path,
+    metadata: &[HarmonyMetadata],
+    opts: &CombineOptions,
+) -> io::Result<CombineReport> {
+    let mut report = CombineReport::default();
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    workbook
+        .add_worksheet()
+        .set_name(FILES_SHEET)
+        .map_err(xlsx_err)?;
+
+    let mut populations: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, m) in metadata.iter().enumerate() {
+        populations.entry(population_label(m)).or_default().push(i);
+    }
+
+    let formats = MetaColumns::detect_formats(metadata, opts)?;
+    let mut rows = vec![0usize; metadata.len()];
+    // sheet names taken so far, lowercased as excel compares them without case
+    let mut used = HashSet::from([FILES_SHEET.to_lowercase()]);
+    // index of the sheet being written, after the files sheet
+    let mut sheet = 0;
+    for (pop, files) in &populations {
+        let hdr = population_header(files.iter().map(|&i| &metadata[i]));
+        let meta = MetaColumns::with_formats(opts, &hdr, formats.clone())?;
+        let mut header = meta.header(false);
+        header.extend(hdr.iter().map(|h| h.as_ref()));
+
+        // fields repeated on every row of a file
+        let file_fields = files
+            .iter()
+            .map(|&i| (i, meta.file_values(&metadata[i], false, &mut report)))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut sheets = 0;
+        let mut row = MAX_ROWS;
+        for_each_row(metadata, files, &hdr, &meta, &mut report, |i, cells| {
+            if row == MAX_ROWS {
+                let name = unused_sheet_name(&mut used, pop, &mut sheets);
+                let ws = workbook.add_worksheet_with_constant_memory();
+                ws.set_name(name).map_err(xlsx_err)?;
+                for (col, name) in header.iter().enumerate() {
+                    ws.write_string_with_format(0, col as u16, *name, &bold)
+                        .map_err(xlsx_err)?;
+                }
+                ws.set_freeze_panes(1, 0).map_err(xlsx_err)?;
+                sheet += 1;
+                row = 1;
+            }
+            let ws = workbook.worksheet_from_index(sheet).map_err(xlsx_err)?;
+            let values = file_fields[&i]
+                .iter()
+                .map(String::as_str)
+                .chain(cells.iter().copied());
+            for (col, v) in values.enumerate() {
+                write_cell(ws, row, col as u16, v).map_err(xlsx_err)?;
+            }
+            row += 1;
+            rows[i] += 1;
+            Ok(())
+        })?;
+    }
+
+    let files = workbook.worksheet_from_index(0).map_err(xlsx_err)?;
+    for (col, name) in FILE_HDRS.iter().enumerate() {
+        files
+            .write_string_with_format(0, col as u16, *name, &bold)
+            .map_err(xlsx_err)?;
+    }
+    files.set_freeze_panes(1, 0).map_err(xlsx_err)?;
+    for (r, (m, n)) in metadata.iter().zip(&rows).enumerate() {
+        let r = r as u32 + 1;
+        let path = m.path.display().to_string();
+        let text = [
+            (0, path.as_str()),
+            (1, m.plate_name.as_str()),
+            (4, m.eval_sig.as_str()),
+            (5, population_label(m)),
+            (6, &m.db_name),
+            (7, &m.db_location),
+        ];
+        for (col, v) in text {
+            files.write_string(r, col, v).map_err(xlsx_err)?;
+        }
+        files.write_number(r, 2, m.measurement).map_err(xlsx_err)?;
+        files.write_number(r, 3, m.evaluation).map_err(xlsx_err)?;
+        files.write_number(r, 8, *n as f64).map_err(xlsx_err)?;
+    }
+
+    workbook.save(path).map_err(xlsx_err)?;
+    Ok(report)
+}
+
+/// Name of a population's `n`th sheet, within Excel's rules for sheet names
+fn sheet_name(population: &str, n: usize) -> String {
+    let mut name = population
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            _ => c,
+        })
+        .collect::<String>();
+    if name.eq_ignore_ascii_case(FILES_SHEET) {
+        name.push_str(" data");
+    }
+    let suffix = if n > 1 {
+        format!(" ({n})")
+    } else {
+        String::new()
+    };
+    let keep = MAX_NAME - suffix.chars().count();
+    if name.chars().count() > keep {
+        name = name.chars().take(keep).collect();
+    }
+    name + &suffix
+}
+
+/// Name of a population's next sheet that no sheet has taken yet, counting on from
+/// its `n`th sheet
+fn unused_sheet_name(used: &mut HashSet<String>, population: &str, n: &mut usize) -> String {
+    loop {
+        *n += 1;
+        let name = sheet_name(population, *n);
+        if used.insert(name.to_lowercase()) {
+            return name;
+        }
+    }
+}
+
+fn write_cell(ws: &mut Worksheet, row: u32, col: u16, v: &str) -> Result<(), XlsxError> {
+    if v.is_empty() {
+        return Ok(());
+    }
+    match v.parse::<f64>() {
+        Ok(n) if n.is_finite() && !has_leading_zero(v) => ws.write_number(row, col, n)?,
+        // missing values, such as NaN, are left blank
+        Ok(n) if !n.is_finite() => ws,
+        _ => ws.write_string(row, col, v)?,
+    };
+    Ok(())
+}
+
+fn xlsx_err(e: XlsxError) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{Datafile, TestDir};
+
+    #[test]
+    fn sheet_names_follow_excel_rules() {
+        assert_eq!(sheet_name("Nuclei [sel]", 1), "Nuclei _sel_");
+        assert_eq!(sheet_name("files", 1), "files data");
+        assert_eq!(sheet_name("Nuclei", 3), "Nuclei (3)");
+        let long = "Cytoplasm Selected - Around Nuclei";
+        assert_eq!(sheet_name(long, 1), &long[..31]);
+        assert_eq!(sheet_name(long, 12), format!("{} (12)", &long[..26]));
+    }
+
+    #[test]
+    fn sheet_names_differing_by_case_get_a_suffix() {
+        let mut used = HashSet::from([FILES_SHEET.to_lowercase()]);
+        let mut next = |pop| unused_sheet_name(&mut used, pop, &mut 0);
+        assert_eq!(next("NUCLEI"), "NUCLEI");
+        assert_eq!(next("Nuclei"), "Nuclei (2)");
+        assert_eq!(next("nuclei"), "nuclei (3)");
+        // the same once shortened
+        assert_eq!(
+            next("Cytoplasm Selected - Around Nuclei A"),
+            "Cytoplasm Selected - Around Nuc"
+        );
+        assert_eq!(
+            next("Cytoplasm Selected - Around Nuclei B"),
+            "Cytoplasm Selected - Around (2)"
+        );
+    }
+
+    #[test]
+    fn workbook_with_a_sheet_per_population() {
+        let dir = TestDir::new();
+        let well = "Row\tColumn\tCount\tCode\n1\t1\t2\t007\n1\t2\tNaN\t12\n";
+        let well = dir.write("p/well.txt", Datafile::new("P1", None, well));
+        dir.write(
+            "p/nuclei.txt",
+            Datafile::new("P1", Some("Nuclei"), "Row\tColumn\n1\t1\n"),
+        );
+
+        let path = dir.root.join("out.xlsx");
+        let report = write_xlsx(&path, &dir.metadata(), &CombineOptions::default()).unwrap();
+        assert_eq!(report.rows[&well], 2);
+        // a zip archive
+        assert!(std::fs::read(&path).unwrap().starts_with(b"PK"));
+    }
+}